@@ -1,4 +1,13 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{
+  borrow::Cow,
+  cell::Cell,
+  collections::{HashMap, HashSet},
+  env, fmt,
+  hash::Hash,
+  io,
+  rc::Rc,
+  str::FromStr,
+};
 
 /// Reserved long flag name for help
 pub const HELP_LONG: &str = "help";
@@ -16,13 +25,30 @@ pub trait Flaggable {
   /// Parses a string into this flag.
   /// The string is value subsequently after the flag
   fn parse_from(&mut self, s: &str) -> Result<(), String>;
+
+  /// Clears values accumulated so far, when the flag is passed with a `-` suffix
+  /// such as `--inc-`. Returns whether this flag can be cleared.
+  fn clear(&mut self) -> bool { false }
+}
+
+/// Describes why a value failed to parse, including the value unless the error mentions it.
+fn invalid(s: &str, e: impl fmt::Display) -> String {
+  let e = e.to_string();
+  if e.contains(s) {
+    e
+  } else {
+    format!("\"{}\": {}", s, e)
+  }
 }
 
 /// Implements flaggable for Option types that wrap things that can be parsed.
-impl<T: FromStr> Flaggable for Option<T> {
+impl<T: FromStr> Flaggable for Option<T>
+where
+  T::Err: fmt::Display,
+{
   fn parse_from(&mut self, s: &str) -> Result<(), String> {
     match T::from_str(s) {
-      Err(_) => Err(s.to_string()),
+      Err(e) => Err(invalid(s, e)),
       Ok(v) => {
         self.replace(v);
         Ok(())
@@ -31,6 +57,60 @@ impl<T: FromStr> Flaggable for Option<T> {
   }
 }
 
+/// Implements flaggable for Vecs, which collect a value for each time the flag is passed.
+impl<T: FromStr> Flaggable for Vec<T>
+where
+  T::Err: fmt::Display,
+{
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    match T::from_str(s) {
+      Err(e) => Err(invalid(s, e)),
+      Ok(v) => {
+        self.push(v);
+        Ok(())
+      },
+    }
+  }
+  fn clear(&mut self) -> bool {
+    Vec::clear(self);
+    true
+  }
+}
+
+/// Implements flaggable for HashMaps, which collect a `key=value` pair each time the flag is passed.
+/// Later values for the same key replace earlier ones.
+impl<K: FromStr + Eq + Hash, V: FromStr> Flaggable for HashMap<K, V>
+where
+  K::Err: fmt::Display,
+  V::Err: fmt::Display,
+{
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let (k, v) = s
+      .split_once('=')
+      .ok_or_else(|| format!("missing '=' in \"{}\"", s))?;
+    let k = K::from_str(k).map_err(|e| invalid(k, e))?;
+    let v = V::from_str(v).map_err(|e| invalid(v, e))?;
+    self.insert(k, v);
+    Ok(())
+  }
+  fn clear(&mut self) -> bool {
+    HashMap::clear(self);
+    true
+  }
+}
+
+/// Implements flaggable for shared cells, so other flags holding a clone can observe the value.
+impl<T: FromStr + Copy> Flaggable for Rc<Cell<T>>
+where
+  T::Err: fmt::Display,
+{
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let v = T::from_str(s).map_err(|e| invalid(s, e))?;
+    self.set(v);
+    Ok(())
+  }
+}
+
 /// Simple indicator for a flag which definitely contains a value.
 #[derive(Debug)]
 pub struct Preset<T>(pub T);
@@ -42,10 +122,17 @@ impl<T> Preset<T> {
   pub fn into_inner(self) -> T { self.0 }
 }
 
-impl<T: FromStr> Flaggable for Preset<T> {
+impl<T: fmt::Display> fmt::Display for Preset<T> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.0.fmt(f) }
+}
+
+impl<T: FromStr> Flaggable for Preset<T>
+where
+  T::Err: fmt::Display,
+{
   fn parse_from(&mut self, s: &str) -> Result<(), String> {
     match T::from_str(s) {
-      Err(_) => Err(s.to_string()),
+      Err(e) => Err(invalid(s, e)),
       Ok(v) => {
         self.0 = v;
         Ok(())
@@ -65,18 +152,142 @@ impl Flaggable for bool {
   }
 }
 
+/// Flag names and help, which are usually static but may be built at runtime.
+type Name = Cow<'static, str>;
+
+type Captures<'a> = &'a mut Vec<(String, Option<String>)>;
+
+type OnFlag<'h> = &'h mut dyn FnMut(&str, Option<&str>);
+
+type Preprocessor<'a> = &'a dyn Fn(Vec<String>) -> Vec<String>;
+
+type OnComplete<'a> = &'a mut dyn FnMut(&str);
+
+type ValueTransform<'a> = &'a dyn Fn(&str) -> String;
+
+type Derive<'a> = &'a dyn Fn(&HashMap<String, String>) -> Option<String>;
+
+/// A registered flag or macro, with its help message.
+struct Entry<'a> {
+  name: Name,
+  help: Name,
+  /// Destination of the flag, which macros do not have.
+  flag: Option<&'a mut dyn Flaggable>,
+}
+
 #[derive(Default)]
 pub struct FlagSet<'a> {
-  mappings: HashMap<&'static str, &'a mut dyn Flaggable>,
-  help_info: HashMap<&'static str, &'static str>,
+  /// Flags and macros in the order they were added.
+  entries: Vec<Entry<'a>>,
+  /// Maps names to their position in `entries`.
+  index: HashMap<Name, usize>,
+  /// Flags which must be passed.
+  required: HashSet<&'static str>,
+  /// Groups of flags where at least one must be passed.
+  required_any: Vec<Vec<&'static str>>,
+  /// Maps short names to the long name they stand for.
+  aliases: HashMap<String, &'static str>,
+  shorts: HashMap<&'static str, char>,
+  /// Raw values of flags seen while parsing.
+  values: HashMap<String, String>,
+  /// Pairs of flags where the first must not be greater than the second.
+  orderings: Vec<(&'static str, &'static str)>,
+  /// Rendered default values of flags, captured when they were added.
+  defaults: HashMap<&'static str, String>,
+  /// Environment variables flags fall back to when not passed.
+  envs: HashMap<&'static str, &'static str>,
+  /// Prefix for environment variables all flags fall back to.
+  env_prefix: Option<&'static str>,
+  /// Names for the values of flags, shown in help.
+  metavars: HashMap<&'static str, &'static str>,
+  /// Subcommands and their summaries, in the order they were added.
+  commands: Vec<(&'static str, &'static str)>,
+  max_tokens: Option<usize>,
+  /// Length after which values shown in help are truncated.
+  max_help_value: Option<usize>,
+  strict_bool: bool,
+  positional_position: PositionalPosition,
+  /// Values allowed for flags, built at runtime.
+  choices: HashMap<&'static str, Vec<String>>,
+  /// Flags which also accept keys after their name, such as `item.0.name`.
+  indexed: HashSet<&'static str>,
+  /// Notes for flags which are deprecated, warned about when they are passed.
+  deprecated: HashMap<&'static str, &'static str>,
+  /// Flags which expand into other arguments.
+  macros: HashMap<&'static str, Vec<String>>,
+  /// Prefixes of unknown flags to capture along with their values.
+  catchalls: Vec<(&'static str, Captures<'a>)>,
+  /// Where errors and help are written, if not stderr.
+  writer: Option<&'a mut dyn io::Write>,
+  /// Rewrites the arguments before they are parsed.
+  preprocessor: Option<Preprocessor<'a>>,
+  /// Rewrites every flag value before it is parsed.
+  value_transform: Option<ValueTransform<'a>>,
+  /// Called with the values of seen flags after a successful parse.
+  on_complete: Vec<(&'static str, OnComplete<'a>)>,
+  /// Computes values for flags which were not passed from the values of others.
+  derived: Vec<(&'static str, Derive<'a>)>,
+  /// Flags whose value in `values` was derived rather than passed.
+  derived_values: HashSet<&'static str>,
+  /// Flags seen by calls to parse since the last completed one.
+  pending: HashSet<String>,
+}
+
+/// How many choices to list when a value is not one of them.
+const SHOWN_CHOICES: usize = 5;
+
+fn transform(t: Option<ValueTransform<'_>>, v: String) -> String {
+  match t {
+    Some(t) => t(&v),
+    None => v,
+  }
 }
 
-fn show_help(h: &HashMap<&str, &str>) {
-  eprintln!("Usage:");
-  h.iter().for_each(|(flag, info)| {
-    eprintln!("  -{}", flag);
-    eprintln!("\t {}", info);
-  });
+/// Reads a value given to a flag which expects none, such as from an env var,
+/// as whether the flag should be applied.
+fn switch(v: &str) -> Result<bool, String> {
+  match v {
+    "1" | "true" | "on" => Ok(true),
+    "0" | "false" | "off" => Ok(false),
+    _ => Err(format!("invalid bool \"{}\"", v)),
+  }
+}
+
+fn check_choice(choices: &[String], v: &str) -> Result<(), String> {
+  if choices.iter().any(|c| c == v) {
+    return Ok(());
+  }
+  let mut msg = format!("\"{}\" is not one of ", v);
+  msg.push_str(&choices[..choices.len().min(SHOWN_CHOICES)].join(", "));
+  if choices.len() > SHOWN_CHOICES {
+    msg.push_str(&format!(", and {} more", choices.len() - SHOWN_CHOICES));
+  }
+  Err(msg)
+}
+
+/// Optional behaviour for a single call to parse.
+#[derive(Default)]
+struct Hooks<'h> {
+  /// Called with each matched flag and its value.
+  on_flag: Option<OnFlag<'h>>,
+  /// Collects unknown flags rather than erroring.
+  unknown: Option<&'h mut Vec<String>>,
+  /// Counts work done while parsing.
+  stats: Option<&'h mut ParseStats>,
+  /// Stops at `--` or the first positional, collecting the arguments after it.
+  tail: Option<&'h mut Vec<String>>,
+  /// Collects warnings rather than writing them.
+  warnings: Option<&'h mut Vec<String>>,
+  /// Leaves env fallbacks and checks for a later call to `complete`.
+  partial: bool,
+}
+
+impl Hooks<'_> {
+  fn flag(&mut self, name: &str, value: Option<&str>) {
+    if let Some(f) = self.on_flag.as_mut() {
+      f(name, value);
+    }
+  }
 }
 
 /// Multiple flags that will be parsed together.
@@ -85,82 +296,1050 @@ impl<'a> FlagSet<'a> {
   /// Creates a new empty FlagSet
   pub fn new() -> Self {
     Self {
-      mappings: HashMap::new(),
-      help_info: HashMap::new(),
+      entries: vec![],
+      index: HashMap::new(),
+      required: HashSet::new(),
+      required_any: vec![],
+      aliases: HashMap::new(),
+      shorts: HashMap::new(),
+      values: HashMap::new(),
+      orderings: vec![],
+      defaults: HashMap::new(),
+      envs: HashMap::new(),
+      env_prefix: None,
+      metavars: HashMap::new(),
+      commands: vec![],
+      max_tokens: None,
+      max_help_value: None,
+      strict_bool: false,
+      positional_position: PositionalPosition::Anywhere,
+      choices: HashMap::new(),
+      indexed: HashSet::new(),
+      deprecated: HashMap::new(),
+      macros: HashMap::new(),
+      catchalls: vec![],
+      writer: None,
+      preprocessor: None,
+      value_transform: None,
+      on_complete: vec![],
+      derived: vec![],
+      derived_values: HashSet::new(),
+      pending: HashSet::new(),
     }
   }
+  /// Limits how many tokens a single parse may process before erroring.
+  pub fn with_max_tokens(mut self, max: usize) -> Self {
+    self.max_tokens = Some(max);
+    self
+  }
+  /// Truncates default values shown in help which are longer than `max` characters,
+  /// ending them with an ellipsis.
+  pub fn with_max_help_value(mut self, max: usize) -> Self {
+    self.max_help_value = Some(max);
+    self
+  }
+  /// Makes passing a flag which expects no value more than once an error,
+  /// rather than toggling it back.
+  pub fn strict_bool(mut self, strict: bool) -> Self {
+    self.strict_bool = strict;
+    self
+  }
+  /// Requires that positionals come before or after all flags,
+  /// erroring on the first positional out of place.
+  pub fn positional_position(mut self, p: PositionalPosition) -> Self {
+    self.positional_position = p;
+    self
+  }
+  /// Sets a function to rewrite all arguments once before they are parsed,
+  /// such as for migrating legacy spellings of flags.
+  pub fn with_preprocessor(mut self, p: Preprocessor<'a>) -> Self {
+    self.preprocessor = Some(p);
+    self
+  }
+  /// Sets a function to rewrite every flag value before it is parsed, including values from
+  /// env vars and `apply_pairs`, such as for lowercasing all input.
+  /// Positionals are left as is, but may be rewritten with `with_preprocessor`.
+  pub fn with_value_transform(mut self, t: ValueTransform<'a>) -> Self {
+    self.value_transform = Some(t);
+    self
+  }
   /// Adds something flaggable with a given name and help message to the flag set.
   /// Panics if the name is one of the reserved help flags(help or h).
   pub fn add<F: Flaggable>(&mut self, name: &'static str, help: &'static str, f: &'a mut F) {
-    self.mappings.insert(name, f);
-    self.help_info.insert(name, help);
+    self.insert(Cow::Borrowed(name), Cow::Borrowed(help), Some(f));
+  }
+  /// Adds something flaggable with a name and help message built at runtime.
+  pub fn add_owned<F: Flaggable>(&mut self, name: String, help: String, f: &'a mut F) {
+    self.insert(Cow::Owned(name), Cow::Owned(help), Some(f));
+  }
+  /// Adds an entry, replacing any with the same name while keeping its position.
+  fn insert(&mut self, name: Name, help: Name, flag: Option<&'a mut dyn Flaggable>) {
+    let entry = Entry {
+      name: name.clone(),
+      help,
+      flag,
+    };
+    match self.index.get(&name) {
+      Some(&i) => self.entries[i] = entry,
+      None => {
+        self.index.insert(name, self.entries.len());
+        self.entries.push(entry);
+      },
+    }
+  }
+  fn entry(&self, name: &str) -> Option<&Entry<'a>> {
+    self.index.get(name).map(|&i| &self.entries[i])
+  }
+  /// Adds something flaggable, capturing its current value as its default.
+  /// The default can be referenced in the help message as `{default}`.
+  pub fn add_default<F: Flaggable + fmt::Display>(
+    &mut self,
+    name: &'static str,
+    help: &'static str,
+    f: &'a mut F,
+  ) {
+    self.defaults.insert(name, f.to_string());
+    self.add(name, help, f);
+  }
+  /// Sets an environment variable to be parsed into a flag when it is not passed.
+  /// The variable can be referenced in the help message as `{env}`.
+  pub fn set_env(&mut self, name: &'static str, var: &'static str) { self.envs.insert(name, var); }
+  /// Requires that a flag is passed, either as an argument or through its env var.
+  /// Checked when a parse completes, so it may be passed in any stage of a staged parse.
+  /// Missing required flags are reported in the order they were added.
+  pub fn set_required(&mut self, name: &'static str) { self.required.insert(name); }
+  /// Requires that at least one of `names` is passed, either as an argument or through its env var,
+  /// such as `--input` or `--stdin`. Flags left at their defaults do not count.
+  pub fn require_any(&mut self, names: &[&'static str]) { self.required_any.push(names.to_vec()); }
+  /// Marks a flag as deprecated, warning with `note` when it is passed,
+  /// such as `use -output instead`.
+  pub fn set_deprecated(&mut self, name: &'static str, note: &'static str) {
+    self.deprecated.insert(name, note);
+  }
+  /// Sets the name shown in help for the value a flag expects.
+  pub fn set_metavar(&mut self, name: &'static str, metavar: &'static str) {
+    self.metavars.insert(name, metavar);
+  }
+  /// Makes every flag without an explicit env var fall back to `PREFIX_NAME`,
+  /// where the name is uppercased with dashes replaced by underscores.
+  pub fn with_env_prefix(mut self, prefix: &'static str) -> Self {
+    self.env_prefix = Some(prefix);
+    self
+  }
+  /// Adds something flaggable under a long name and an optional short name.
+  /// Both `-s` and `--long` will parse into the same destination.
+  /// Panics if the short name is `h`, which is reserved for help, or is taken by another flag.
+  pub fn add_named<F: Flaggable>(
+    &mut self,
+    short: Option<char>,
+    long: &'static str,
+    help: &'static str,
+    f: &'a mut F,
+  ) {
+    if let Some(s) = short {
+      let s = s.to_string();
+      let taken = match self.aliases.get(&s) {
+        Some(&other) => other != long,
+        None => self.index.contains_key(s.as_str()),
+      };
+      assert!(
+        s != HELP_SHORT && !taken,
+        "short flag -{} is already taken",
+        s
+      );
+    }
+    self.add(long, help, f);
+    if let Some(s) = short {
+      self.aliases.insert(s.to_string(), long);
+      self.shorts.insert(long, s);
+    }
+  }
+  /// Adds something flaggable which only accepts values in `choices`,
+  /// such as names read from a file at startup.
+  pub fn add_dynamic_choice<F: Flaggable>(
+    &mut self,
+    name: &'static str,
+    help: &'static str,
+    choices: Vec<String>,
+    f: &'a mut F,
+  ) {
+    self.choices.insert(name, choices);
+    self.add(name, help, f);
+  }
+  /// Adds something flaggable which also accepts keys after its name,
+  /// such as `--item.0.name foo`, which is parsed as `0.name=foo`.
+  /// Usually used with `IndexedMap`.
+  pub fn add_indexed<F: Flaggable>(
+    &mut self,
+    name: &'static str,
+    help: &'static str,
+    f: &'a mut F,
+  ) {
+    self.indexed.insert(name);
+    self.add(name, help, f);
+  }
+  /// Adds a flag which, when passed, is replaced by `expansions` in the arguments,
+  /// such as `--fast` standing for `--threads 8 --cache on`.
+  /// Expansions may include other macros, but a macro which expands to itself
+  /// errors when it is passed.
+  pub fn add_macro(&mut self, name: &'static str, help: &'static str, expansions: &[&str]) {
+    self.insert(Cow::Borrowed(name), Cow::Borrowed(help), None);
+    self
+      .macros
+      .insert(name, expansions.iter().map(|e| e.to_string()).collect());
+  }
+  /// Whether expanding a macro would eventually expand it again.
+  fn is_recursive(&self, name: &str) -> bool {
+    let mut stack = vec![name];
+    let mut visited = HashSet::new();
+    while let Some(m) = stack.pop() {
+      for e in self.macros.get(m).into_iter().flatten() {
+        if !e.starts_with('-') {
+          continue;
+        }
+        let e = e.trim_start_matches('-');
+        let e = self.resolve(e);
+        if e == name {
+          return true;
+        }
+        if visited.insert(e) {
+          stack.push(e);
+        }
+      }
+    }
+    false
+  }
+  /// Captures any unregistered flag starting with `prefix` into `dest`, instead of erroring.
+  /// Each capture is the flag name without the prefix, along with its value,
+  /// which is either given as `--prefix-name=value` or is the next argument if it is not a flag.
+  pub fn add_prefix_catchall(&mut self, prefix: &'static str, dest: Captures<'a>) {
+    self.catchalls.push((prefix.trim_start_matches('-'), dest));
+  }
+  /// Calls `f` with the raw value of a flag after a parse completes successfully,
+  /// if the flag was seen since the last completed parse.
+  /// Callbacks run in the order their flags were added, so side effects can be deferred
+  /// until all arguments are known to be valid.
+  pub fn add_on_complete(&mut self, name: &'static str, f: OnComplete<'a>) {
+    self.on_complete.push((name, f));
+  }
+  /// Sets the value of a flag which was not passed to the result of `f`, if any,
+  /// such as deriving `--output` from `--input`.
+  /// `f` is called after parsing with the raw values of seen flags, including earlier derived ones.
+  /// Derived values are recomputed on each parse, replacing the previous derived value in
+  /// flags which can be cleared, and do not count as passing the flag
+  /// for `set_required` and `require_any`.
+  pub fn add_derived_default(&mut self, name: &'static str, f: Derive<'a>) {
+    self.derived.push((name, f));
+  }
+  /// Adds a subcommand with a summary to be listed in help.
+  /// Use `parse_with_command` to find which command was passed.
+  pub fn add_command(&mut self, name: &'static str, summary: &'static str) {
+    self.commands.push((name, summary));
+  }
+  /// Requires that the value of flag `min` is not greater than the value of flag `max`.
+  /// Checked after parsing if both flags were passed.
+  pub fn add_ordering(&mut self, min: &'static str, max: &'static str) {
+    self.orderings.push((min, max));
+  }
+  /// The flag a name refers to, preferring flags with that name over short aliases.
+  fn resolve<'n>(&self, name: &'n str) -> &'n str {
+    match self.aliases.get(name) {
+      Some(&long) if !self.index.contains_key(name) => long,
+      _ => name,
+    }
+  }
+  /// Whether a flag, with or without leading dashes, is registered in this set.
+  pub fn is_registered(&self, name: &str) -> bool {
+    let name = name.trim_start_matches('-');
+    self.index.contains_key(name) || self.aliases.contains_key(name)
+  }
+  /// Parses the raw value last passed for a flag, if it was seen while parsing.
+  pub fn value_of<T: FromStr>(&self, name: &str) -> Option<T> {
+    self.values.get(name).and_then(|v| T::from_str(v).ok())
+  }
+  /// Names of all flags and macros, in the order they were added.
+  pub fn flag_names(&self) -> Vec<&str> { self.entries.iter().map(|e| e.name.as_ref()).collect() }
+  /// Names of all flags and macros, sorted.
+  fn sorted_names(&self) -> Vec<&str> {
+    let mut names = self.flag_names();
+    names.sort_unstable();
+    names
   }
-  /// Parses an iterator of strings into this flag set.
+  /// How a flag is written in help, including its short name and metavar.
+  fn flag_usage(&self, flag: &str) -> String {
+    let mut out = match self.shorts.get(flag) {
+      Some(s) => format!("-{}, --{}", s, flag),
+      None => format!("-{}", flag),
+    };
+    if let Some(m) = self.metavars.get(flag) {
+      out.push(' ');
+      out.push_str(m);
+    }
+    out
+  }
+  /// Default value of a flag as shown in help, truncated if it is too long.
+  fn shown_default(&self, flag: &str) -> Option<String> {
+    let d = self.defaults.get(flag)?;
+    match self.max_help_value {
+      Some(max) if d.chars().count() > max => {
+        let mut d: String = d.chars().take(max).collect();
+        d.push('…');
+        Some(d)
+      },
+      _ => Some(d.clone()),
+    }
+  }
+  /// Help message for a flag with placeholders substituted.
+  fn flag_help(&self, flag: &str) -> String {
+    let mut info = self
+      .entry(flag)
+      .map_or_else(String::new, |e| e.help.to_string());
+    if let Some(d) = self.shown_default(flag) {
+      info = info.replace("{default}", &d);
+    }
+    if let Some(e) = self.env_var(flag) {
+      info = info.replace("{env}", &e);
+    }
+    info
+  }
+  /// The environment variable a flag falls back to, set explicitly or through the env prefix.
+  fn env_var(&self, flag: &str) -> Option<String> {
+    if let Some(var) = self.envs.get(flag) {
+      return Some(var.to_string());
+    }
+    let prefix = self
+      .env_prefix
+      .filter(|_| self.entry(flag).is_some_and(|e| e.flag.is_some()))?;
+    let name = flag.to_uppercase().replace(['-', '.'], "_");
+    Some(format!("{}_{}", prefix, name))
+  }
+  /// Renders the help info for all flags in this set, in the order they were added.
+  pub fn help_string(&self) -> String {
+    let mut out = String::from("Usage:\n");
+    self.flag_names().into_iter().for_each(|flag| {
+      out.push_str(&format!("  {}\n", self.flag_usage(flag)));
+      out.push_str(&format!("\t {}", self.flag_help(flag)));
+      if let Some(e) = self.env_var(flag) {
+        out.push_str(&format!(" [env: {}]", e));
+      }
+      out.push('\n');
+    });
+    if !self.commands.is_empty() {
+      out.push_str("Commands:\n");
+    }
+    for (name, summary) in &self.commands {
+      out.push_str(&format!("  {}\n\t {}\n", name, summary));
+    }
+    out
+  }
+  /// Name, whether it expects a value, and help for each flag, sorted by name.
+  /// Intended for generating shell completions.
+  pub fn completion_spec(&self) -> Vec<(String, bool, &str)> {
+    self
+      .sorted_names()
+      .into_iter()
+      .filter_map(|name| {
+        let entry = self.entry(name)?;
+        let expects_value = entry.flag.as_ref()?.expects_value();
+        Some((name.to_string(), expects_value, entry.help.as_ref()))
+      })
+      .collect()
+  }
+  /// Renders a zsh completion function for a program using these flags.
+  pub fn zsh_completion(&self, prog: &str) -> String {
+    let mut out = format!("#compdef {}\n\n_{}() {{\n  _arguments", prog, prog);
+    let escape = |s: &str| s.replace('\'', "'\\''").replace(':', "\\:");
+    for (name, expects_value, help) in self.completion_spec() {
+      // Descriptions must stay on one line.
+      let help = help.split_whitespace().collect::<Vec<_>>().join(" ");
+      let help = escape(&help).replace('[', "\\[").replace(']', "\\]");
+      // Flags with a short name complete as either form, but only one of them.
+      let spec = match self.shorts.get(name.as_str()) {
+        Some(s) => format!("'(-{0} --{1})'{{-{0},--{1}}}'", s, name),
+        None => format!("'-{}", name),
+      };
+      out.push_str(&format!(" \\\n    {}[{}]", spec, help));
+      if expects_value {
+        let metavar = self.metavars.get(name.as_str()).copied().unwrap_or("value");
+        out.push_str(&format!(":{}:", escape(metavar)));
+      }
+      out.push('\'');
+    }
+    out.push_str(&format!("\n}}\n\n_{} \"$@\"\n", prog));
+    out
+  }
+  /// Compares the flags of this set to another, sorted by name.
+  /// Added flags are those only in `other`, removed are those only in `self`.
+  pub fn diff(&self, other: &FlagSet<'_>) -> FlagDiff {
+    let mut diff = FlagDiff::default();
+    for name in self.sorted_names() {
+      match (other.entry(name), self.entry(name)) {
+        (None, _) => diff.removed.push(name.to_string()),
+        (Some(new), Some(old)) if new.help != old.help => diff.help_changed.push(name.to_string()),
+        (Some(_), _) => {},
+      }
+    }
+    for name in other.sorted_names() {
+      if !self.index.contains_key(name) {
+        diff.added.push(name.to_string());
+      }
+    }
+    diff
+  }
+  /// Renders the OPTIONS section of a man page in roff, with a `.TP` entry per flag
+  /// in the order they were added.
+  pub fn manpage_options(&self) -> String {
+    let escape = |s: &str| s.replace('\\', "\\e").replace('-', "\\-");
+    let mut out = String::from(".SH OPTIONS\n");
+    for flag in self.flag_names() {
+      out.push_str(".TP\n");
+      if let Some(s) = self.shorts.get(flag) {
+        out.push_str(&format!("\\fB\\-{}\\fR, \\fB\\-\\-{}\\fR", s, escape(flag)));
+      } else {
+        out.push_str(&format!("\\fB\\-{}\\fR", escape(flag)));
+      }
+      if let Some(m) = self.metavars.get(flag) {
+        out.push_str(&format!(" \\fI{}\\fR", escape(m)));
+      }
+      out.push('\n');
+      let help = escape(&self.flag_help(flag));
+      // Lines starting with a dot or quote would be read as requests.
+      if help.starts_with(['.', '\'']) {
+        out.push_str("\\&");
+      }
+      out.push_str(&help);
+      out.push('\n');
+    }
+    out
+  }
+  /// Checks that the flags of this set exactly match `schema`, such as in a test
+  /// asserting that a CLI has not drifted. Returns a description of each difference.
+  pub fn matches_schema(&self, schema: &[FlagSpec]) -> Result<(), Vec<String>> {
+    let mut errs = vec![];
+    for spec in schema {
+      let (flag, help) = match self.entry(spec.name) {
+        Some(Entry {
+          flag: Some(flag),
+          help,
+          ..
+        }) => (flag, help),
+        _ => {
+          errs.push(format!("missing flag -{}", spec.name));
+          continue;
+        },
+      };
+      if flag.expects_value() != spec.expects_value {
+        let expects = if spec.expects_value { "a" } else { "no" };
+        errs.push(format!(
+          "flag -{} should expect {} value",
+          spec.name, expects
+        ));
+      }
+      if help.is_empty() == spec.has_help {
+        let has = if spec.has_help { "" } else { "no " };
+        errs.push(format!("flag -{} should have {}help", spec.name, has));
+      }
+    }
+    for entry in self.entries.iter().filter(|e| e.flag.is_some()) {
+      if !schema.iter().any(|s| s.name == entry.name) {
+        errs.push(format!("unexpected flag -{}", entry.name));
+      }
+    }
+    if errs.is_empty() {
+      Ok(())
+    } else {
+      Err(errs)
+    }
+  }
+  /// Renders a Markdown table of all flags in the order they were added,
+  /// for embedding in documentation.
+  pub fn help_markdown(&self) -> String {
+    let mut out = String::from("| Flag | Value | Description | Default |\n|---|---|---|---|\n");
+    for flag in self.flag_names() {
+      let usage = match self.shorts.get(flag) {
+        Some(s) => format!("-{}, --{}", s, flag),
+        None => format!("-{}", flag),
+      };
+      let value = match (
+        self.metavars.get(flag),
+        self.entry(flag).and_then(|e| e.flag.as_ref()),
+      ) {
+        (Some(m), _) => format!("`{}`", m.replace('|', "\\|")),
+        (None, Some(f)) if f.expects_value() => String::from("`value`"),
+        _ => String::new(),
+      };
+      let default = self
+        .shown_default(flag)
+        .map_or_else(String::new, |d| format!("`{}`", d.replace('|', "\\|")));
+      out.push_str(&format!(
+        "| `{}` | {} | {} | {} |\n",
+        usage,
+        value,
+        self.flag_help(flag).replace('|', "\\|"),
+        default
+      ));
+    }
+    out
+  }
+  /// Forgets which flags were seen by previous calls to parse.
+  /// Values already parsed into destinations are left as is.
+  pub fn reset(&mut self) {
+    self.values.clear();
+    self.derived_values.clear();
+    self.pending.clear();
+  }
+  /// Parses an iterator of strings into this flag set, then calls `complete`.
   /// Returns unmatched values from parsing or an error.
-  pub fn parse<I>(&mut self, mut i: I) -> Result<Vec<String>, ParseError>
+  /// Flags seen in earlier calls count as seen until `reset` is called.
+  /// For parsing in stages, use `parse_partial` for all but the last stage,
+  /// so env fallbacks and checks apply once to the combined arguments.
+  pub fn parse<I>(&mut self, i: I) -> Result<Vec<String>, ParseError>
+  where
+    I: Iterator<Item = String>, {
+    self.parse_with(i, Hooks::default())
+  }
+  /// Parses like `parse`, but without calling `complete`, for an early stage of a parse
+  /// which is finished by a later call to `parse` or `complete`.
+  pub fn parse_partial<I>(&mut self, i: I) -> Result<Vec<String>, ParseError>
+  where
+    I: Iterator<Item = String>, {
+    let hooks = Hooks {
+      partial: true,
+      ..Hooks::default()
+    };
+    self.parse_with(i, hooks)
+  }
+  /// Parses like `parse`, also calling `f` with the name and raw value of each matched flag,
+  /// in the order they were passed. Flags which expect no value are passed `None`.
+  pub fn parse_dispatch<I, F>(&mut self, i: I, mut f: F) -> Result<Vec<String>, ParseError>
+  where
+    I: Iterator<Item = String>,
+    F: FnMut(&str, Option<&str>), {
+    let hooks = Hooks {
+      on_flag: Some(&mut f),
+      ..Hooks::default()
+    };
+    self.parse_with(i, hooks)
+  }
+  /// Parses like `parse`, but collects unknown flags instead of erroring on them.
+  /// Returns unmatched values and the names of unknown flags.
+  /// Since it is not known whether an unknown flag expects a value,
+  /// any argument following one is treated as unmatched.
+  pub fn parse_lenient<I>(&mut self, i: I) -> Result<(Vec<String>, Vec<String>), ParseError>
+  where
+    I: Iterator<Item = String>, {
+    let mut unknown = vec![];
+    let hooks = Hooks {
+      unknown: Some(&mut unknown),
+      ..Hooks::default()
+    };
+    let rem = self.parse_with(i, hooks)?;
+    Ok((rem, unknown))
+  }
+  /// Parses like `parse`, also returning counts of the work done while parsing.
+  pub fn parse_with_stats<I>(&mut self, i: I) -> Result<(Vec<String>, ParseStats), ParseError>
+  where
+    I: Iterator<Item = String>, {
+    let mut stats = ParseStats::default();
+    let hooks = Hooks {
+      stats: Some(&mut stats),
+      ..Hooks::default()
+    };
+    let rem = self.parse_with(i, hooks)?;
+    Ok((rem, stats))
+  }
+  /// Parses flags until `--` or the first positional, and returns the arguments after it,
+  /// including the positional but not `--`, such as for passing to another FlagSet.
+  pub fn parse_until<I>(&mut self, i: I) -> Result<std::vec::IntoIter<String>, ParseError>
+  where
+    I: Iterator<Item = String>, {
+    let mut tail = vec![];
+    let hooks = Hooks {
+      tail: Some(&mut tail),
+      ..Hooks::default()
+    };
+    self.parse_with(i, hooks)?;
+    Ok(tail.into_iter())
+  }
+  /// Parses like `parse`, but returns warnings, such as for deprecated flags,
+  /// instead of writing them.
+  pub fn parse_collect_warnings<I>(
+    &mut self,
+    i: I,
+  ) -> Result<(Vec<String>, Vec<String>), ParseError>
+  where
+    I: Iterator<Item = String>, {
+    let mut warnings = vec![];
+    let hooks = Hooks {
+      warnings: Some(&mut warnings),
+      ..Hooks::default()
+    };
+    let rem = self.parse_with(i, hooks)?;
+    Ok((rem, warnings))
+  }
+  fn parse_with<I>(&mut self, i: I, mut hooks: Hooks<'_>) -> Result<Vec<String>, ParseError>
   where
     I: Iterator<Item = String>, {
     let mut out = vec![];
-    while let Some(v) = i.next() {
-      if !v.starts_with('-') {
+    let args: Vec<String> = i.collect();
+    let mut i = match &self.preprocessor {
+      Some(p) => p(args),
+      None => args,
+    }
+    .into_iter();
+    let limit = self.max_tokens.unwrap_or(usize::MAX);
+    let mut tokens = 0;
+    let mut lookups = 0;
+    let mut seen_flag = false;
+    let mut warnings = vec![];
+    // Flags seen by this call, as `values` also holds those from earlier calls.
+    let mut seen = HashSet::new();
+    let mut next = |i: &mut std::vec::IntoIter<String>| match i.next() {
+      Some(_) if tokens == limit => Err(ParseError::TooManyArguments),
+      Some(v) => {
+        tokens += 1;
+        Ok(Some(v))
+      },
+      None => Ok(None),
+    };
+    while let Some(v) = next(&mut i)? {
+      if let Some(tail) = hooks.tail.as_mut() {
+        if v == "--" || !v.starts_with('-') || v == "-" {
+          if v != "--" {
+            tail.push(v);
+          }
+          tail.extend(&mut i);
+          break;
+        }
+      }
+      // A lone dash conventionally means stdin, so it is kept as a value.
+      if !v.starts_with('-') || v == "-" {
+        if self.positional_position == PositionalPosition::Before && seen_flag {
+          return Err(ParseError::MisplacedPositional(
+            v,
+            PositionalPosition::Before,
+          ));
+        }
         out.push(v);
         continue;
       }
+      seen_flag = true;
+      match out.last() {
+        Some(p) if self.positional_position == PositionalPosition::After => {
+          return Err(ParseError::MisplacedPositional(
+            p.clone(),
+            PositionalPosition::After,
+          ))
+        },
+        _ => {},
+      }
       let v = v.trim_start_matches('-');
-      match self.mappings.get_mut(&*v) {
-        Some(ref mut flag) => {
+      lookups += 1;
+      let indexed = v.split_once('.').filter(|(n, _)| self.indexed.contains(n));
+      let name = self.resolve(v);
+      let list = v.strip_suffix('-').map(|list| self.resolve(list));
+      let entries = &mut self.entries;
+      if let Some((name, key)) = indexed {
+        let flag_val = match next(&mut i)? {
+          None => return Err(ParseError::MissingValue(v.to_string())),
+          Some(flag_val) => transform(self.value_transform, flag_val),
+        };
+        if let Some(flag) = self.index.get(name).and_then(|&i| entries[i].flag.as_mut()) {
+          flag
+            .parse_from(&format!("{}={}", key, flag_val))
+            .map_err(|e| ParseError::ParseFromFailure(v.to_string(), e))?;
+        }
+        hooks.flag(v, Some(&flag_val));
+        seen.insert(name.to_string());
+        self.values.insert(name.to_string(), flag_val);
+        continue;
+      }
+      if let Some(note) = self.deprecated.get(name) {
+        warnings.push(format!("flag -{} is deprecated: {}", v, note));
+      }
+      let cleared = match list {
+        Some(list) if !self.index.contains_key(name) => {
+          let flag = self.index.get(list).and_then(|&i| entries[i].flag.as_mut());
+          flag.and_then(|flag| flag.clear().then_some(list))
+        },
+        _ => None,
+      };
+      // A cleared flag no longer counts as passed.
+      if let Some(list) = cleared {
+        seen.remove(list);
+        self.pending.remove(list);
+        self.values.remove(list);
+        continue;
+      }
+      let flag = self.index.get(name).map(|&i| &mut entries[i].flag);
+      match flag {
+        Some(Some(flag)) => {
           if !flag.expects_value() {
+            if self.strict_bool && self.values.contains_key(name) {
+              return Err(ParseError::DuplicateFlag(v.to_string()));
+            }
             flag
               .parse_from("")
               .map_err(|e| ParseError::ParseFromFailure(v.to_string(), e))?;
+            hooks.flag(name, None);
+            seen.insert(name.to_string());
+            self.values.insert(name.to_string(), String::new());
             continue;
           }
-          let flag_val = match i.next() {
+          let flag_val = match next(&mut i)? {
             None => return Err(ParseError::MissingValue(v.to_string())),
-            Some(flag_val) => flag_val,
+            Some(flag_val) => transform(self.value_transform, flag_val),
           };
+          if let Some(choices) = self.choices.get(name) {
+            check_choice(choices, &flag_val)
+              .map_err(|e| ParseError::ParseFromFailure(v.to_string(), e))?;
+          }
           flag
             .parse_from(&flag_val)
             .map_err(|e| ParseError::ParseFromFailure(v.to_string(), e))?;
+          hooks.flag(name, Some(&flag_val));
+          seen.insert(name.to_string());
+          self.values.insert(name.to_string(), flag_val);
+        },
+        _ if self.macros.contains_key(name) => {
+          if self.is_recursive(name) {
+            return Err(ParseError::RecursiveMacro(name.to_string()));
+          }
+          let expanded = self.macros[name].iter().cloned().chain(i);
+          i = expanded.collect::<Vec<_>>().into_iter();
+        },
+        _ if v == HELP_LONG || v == HELP_SHORT => return Err(ParseError::HelpRequested),
+        _ => {
+          let found = self.catchalls.iter_mut().find_map(|(prefix, dest)| {
+            let rest = v.strip_prefix(*prefix).filter(|r| !r.is_empty())?;
+            Some((dest, rest))
+          });
+          let (dest, rest) = match (found, hooks.unknown.as_mut()) {
+            (Some(found), _) => found,
+            (None, Some(unknown)) => {
+              unknown.push(v.to_string());
+              continue;
+            },
+            (None, None) => return Err(ParseError::UnknownFlag(v.to_string())),
+          };
+          let takes_next = i.as_slice().first().is_some_and(|n| !n.starts_with('-'));
+          let (key, value) = match rest.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None if takes_next => (rest, next(&mut i)?),
+            None => (rest, None),
+          };
+          dest.push((key.to_string(), value));
         },
-        None if v == HELP_LONG || v == HELP_SHORT => return Err(ParseError::HelpRequested),
-        None => return Err(ParseError::UnknownFlag(v.to_string())),
       };
     }
+    if let Some(stats) = hooks.stats {
+      stats.tokens = tokens;
+      stats.lookups = lookups;
+    }
+    match hooks.warnings {
+      Some(collected) => collected.extend(warnings),
+      None => self.warn(&warnings),
+    }
+    self.pending.extend(seen);
+    if !hooks.partial {
+      self.complete()?;
+    }
     Ok(out)
   }
+  /// Finishes a parse made of calls to `parse_partial`, applying env fallbacks and derived
+  /// defaults, checking constraints such as required flags, and then calling completion
+  /// callbacks for flags seen since the last completed parse.
+  pub fn complete(&mut self) -> Result<(), ParseError> {
+    let res = self.finish();
+    if res.is_ok() {
+      for entry in &self.entries {
+        let value = match self.values.get(entry.name.as_ref()) {
+          Some(value) if self.pending.contains(entry.name.as_ref()) => value,
+          _ => continue,
+        };
+        for (name, f) in self.on_complete.iter_mut() {
+          if *name == entry.name {
+            f(value);
+          }
+        }
+      }
+    }
+    self.pending.clear();
+    res
+  }
+  /// Applies env fallbacks and derived defaults, and checks constraints.
+  fn finish(&mut self) -> Result<(), ParseError> {
+    // Values derived by earlier calls are stale unless the flag was passed since,
+    // so clear them from flags such as `Vec` which would otherwise accumulate them.
+    let entries = &mut self.entries;
+    for name in self.derived_values.drain() {
+      if self.pending.contains(name) {
+        continue;
+      }
+      self.values.remove(name);
+      if let Some(flag) = self.index.get(name).and_then(|&i| entries[i].flag.as_mut()) {
+        flag.clear();
+      }
+    }
+    for i in 0..self.entries.len() {
+      let name = self.entries[i].name.clone();
+      if self.values.contains_key(name.as_ref()) {
+        continue;
+      }
+      let val = match self.env_var(&name).map(env::var) {
+        Some(Ok(val)) => transform(self.value_transform, val),
+        _ => continue,
+      };
+      if let Some(flag) = self.entries[i].flag.as_mut() {
+        let err = |e| ParseError::ParseFromFailure(name.to_string(), e);
+        if flag.expects_value() {
+          flag.parse_from(&val).map_err(err)?;
+        } else if switch(&val).map_err(err)? {
+          flag.parse_from("").map_err(err)?;
+        } else {
+          continue;
+        }
+      }
+      self.pending.insert(name.to_string());
+      self.values.insert(name.to_string(), val);
+    }
+    let entries = &mut self.entries;
+    for &(name, f) in &self.derived {
+      if self.values.contains_key(name) {
+        continue;
+      }
+      let val = match f(&self.values) {
+        Some(val) => val,
+        None => continue,
+      };
+      if let Some(flag) = self.index.get(name).and_then(|&i| entries[i].flag.as_mut()) {
+        flag
+          .parse_from(&val)
+          .map_err(|e| ParseError::ParseFromFailure(name.to_string(), e))?;
+      }
+      self.derived_values.insert(name);
+      self.values.insert(name.to_string(), val);
+    }
+    let provided = |n: &str| self.values.contains_key(n) && !self.derived_values.contains(n);
+    let missing: Vec<String> = self
+      .flag_names()
+      .into_iter()
+      .filter(|n| self.required.contains(n) && !provided(n))
+      .map(String::from)
+      .collect();
+    if !missing.is_empty() {
+      return Err(ParseError::MissingRequired(missing));
+    }
+    for group in &self.required_any {
+      if !group.iter().any(|n| provided(n)) {
+        return Err(ParseError::NoneProvided(
+          group.iter().map(|n| n.to_string()).collect(),
+        ));
+      }
+    }
+    for &(min, max) in &self.orderings {
+      match (self.value_of::<f64>(min), self.value_of::<f64>(max)) {
+        (Some(lo), Some(hi)) if lo > hi => {
+          return Err(ParseError::OrderViolation(min.to_string(), max.to_string()))
+        },
+        _ => {},
+      }
+    }
+    Ok(())
+  }
+  /// Parses each value into the flag with its paired name, such as for pairs from a web form.
+  /// Flags which expect no value are applied for `1`, `true` or `on`, and skipped for `0`,
+  /// `false` or `off`. Names are given without dashes, and flags count as seen for later calls to parse,
+  /// but env fallbacks and other checks are not applied.
+  pub fn apply_pairs(&mut self, pairs: &[(String, String)]) -> Result<(), ParseError> {
+    for (name, value) in pairs {
+      let name = self.resolve(name);
+      let value = transform(self.value_transform, value.clone());
+      let entries = &mut self.entries;
+      let flag = match self.index.get(name).and_then(|&i| entries[i].flag.as_mut()) {
+        Some(flag) => flag,
+        None => return Err(ParseError::UnknownFlag(name.to_string())),
+      };
+      if let Some(choices) = self.choices.get(name) {
+        check_choice(choices, &value)
+          .map_err(|e| ParseError::ParseFromFailure(name.to_string(), e))?;
+      }
+      let err = |e| ParseError::ParseFromFailure(name.to_string(), e);
+      if flag.expects_value() {
+        flag.parse_from(&value).map_err(err)?;
+      } else if switch(&value).map_err(err)? {
+        flag.parse_from("").map_err(err)?;
+      } else {
+        continue;
+      }
+      self.derived_values.remove(name);
+      self.values.insert(name.to_string(), value);
+    }
+    Ok(())
+  }
+  /// Parses like `parse`, but splits off the first unmatched value as a command name.
+  /// Returns the command, if any, along with the remaining unmatched values.
+  pub fn parse_with_command<I>(
+    &mut self,
+    i: I,
+  ) -> Result<(Option<String>, Vec<String>), ParseError>
+  where
+    I: Iterator<Item = String>, {
+    let mut rem = self.parse(i)?;
+    if rem.is_empty() {
+      return Ok((None, rem));
+    }
+    let cmd = rem.remove(0);
+    Ok((Some(cmd), rem))
+  }
+  /// Parses like `parse`, but converts the unmatched values into typed positionals.
+  pub fn expect_positionals<P: Positionals, I>(&mut self, i: I) -> Result<P, ParseError>
+  where
+    I: Iterator<Item = String>, {
+    P::from_positionals(self.parse(i)?)
+  }
+  /// Parses like `parse`, but attaches the rendered help to any error.
+  pub fn parse_or_help<I>(&mut self, i: I) -> Result<Vec<String>, (ParseError, String)>
+  where
+    I: Iterator<Item = String>, {
+    self.parse(i).map_err(|e| (e, self.help_string()))
+  }
+  /// Parses argument from env::args without the program name, like `parse_or_help`.
+  /// Unlike `parse_args`, errors are returned for the caller to display.
+  pub fn parse_args_or_help(&mut self) -> Result<Vec<String>, (ParseError, String)> {
+    self.parse_or_help(env::args().skip(1))
+  }
+  /// Sets where `run` and `parse_args` write errors and help to, instead of stderr.
+  /// Warnings from any parse are also written to it.
+  pub fn with_writer(mut self, w: &'a mut dyn io::Write) -> Self {
+    self.writer = Some(w);
+    self
+  }
+  /// Writes warnings to the writer, or stderr if there is none.
+  fn warn(&mut self, warnings: &[String]) {
+    let mut stderr = io::stderr();
+    let w: &mut dyn io::Write = match self.writer.as_mut() {
+      Some(w) => &mut **w,
+      None => &mut stderr,
+    };
+    for warning in warnings {
+      let _ = writeln!(w, "warning: {}", warning);
+    }
+  }
+  /// Writes an error along with help to the writer, and returns the exit status for it.
+  fn report(&mut self, e: ParseError) -> i32 {
+    const OK: i32 = 0;
+    const FAILURE: i32 = 1;
+    let msg = e.message();
+    let help = self.help_string();
+    let mut stderr = io::stderr();
+    let w: &mut dyn io::Write = match self.writer.as_mut() {
+      Some(w) => &mut **w,
+      None => &mut stderr,
+    };
+    if msg.is_empty() {
+      let _ = write!(w, "{}", help);
+      return OK;
+    }
+    let _ = write!(w, "{}\n{}", msg, help);
+    FAILURE
+  }
+  /// Parses argument from env::args without the program name, like `parse_args`,
+  /// but returns the exit status instead of exiting.
+  /// On failure no arguments are returned, and the status is non-zero.
+  /// If help was requested, no arguments are returned and the status is zero.
+  pub fn run(&mut self) -> (Vec<String>, i32) { self.run_from(env::args().skip(1)) }
+  /// Parses like `run`, from an iterator of strings.
+  pub fn run_from<I>(&mut self, i: I) -> (Vec<String>, i32)
+  where
+    I: Iterator<Item = String>, {
+    match self.parse(i) {
+      Ok(rem) => (rem, 0),
+      Err(e) => (vec![], self.report(e)),
+    }
+  }
   /// Parses argument from env::args without the program name.
-  /// Exits on failure, and displays help info to stderr.
+  /// Exits on failure, and displays help info to stderr or the configured writer.
   /// Returns extra arguments which were not used in parsing.
   pub fn parse_args(&mut self) -> Vec<String> {
-    use std::env::args;
-    const OK: i32 = 0;
-    const FAILURE: i32 = 1;
-    match self.parse(args().skip(1)) {
+    match self.parse(env::args().skip(1)) {
       Ok(rem) => rem,
       Err(e) => {
-        let status = match e {
-          ParseError::HelpRequested => OK,
-          ParseError::ParseFromFailure(f, v) => {
-            eprintln!("Invalid value \"{}\" for flag -{}", f, v);
-            FAILURE
-          },
-          ParseError::UnknownFlag(f) => {
-            eprintln!("flag provided but not defined: -{}", f);
-            FAILURE
-          },
-          ParseError::MissingValue(f) => {
-            eprintln!("Missing value for flag: -{}", f);
-            FAILURE
-          },
-        };
-        show_help(&self.help_info);
+        let status = self.report(e);
         std::process::exit(status);
       },
     }
   }
 }
 
+/// Counts of the work done by `FlagSet::parse_with_stats`, for diagnosing slow parses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ParseStats {
+  /// Arguments processed, including flag values and macro expansions.
+  pub tokens: usize,
+  /// Flag names looked up.
+  pub lookups: usize,
+}
+
+/// An expected flag, checked by `FlagSet::matches_schema`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FlagSpec {
+  pub name: &'static str,
+  pub expects_value: bool,
+  pub has_help: bool,
+}
+
+/// Differences between the flags of two FlagSets, from `FlagSet::diff`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlagDiff {
+  pub added: Vec<String>,
+  pub removed: Vec<String>,
+  pub help_changed: Vec<String>,
+}
+
+/// Types which can be built from the values left unmatched after parsing flags.
+/// Implemented for tuples of up to six types which implement FromStr.
+pub trait Positionals: Sized {
+  fn from_positionals(v: Vec<String>) -> Result<Self, ParseError>;
+}
+
+macro_rules! impl_positionals {
+  ($($t: ident),+) => {
+    impl<$($t: FromStr),+> Positionals for ($($t,)+) {
+      fn from_positionals(v: Vec<String>) -> Result<Self, ParseError> {
+        let expected = [$(stringify!($t)),+].len();
+        if v.len() != expected {
+          return Err(ParseError::PositionalCount(expected, v.len()));
+        }
+        let mut v = v.into_iter();
+        Ok(($({
+          let s = v.next().unwrap();
+          $t::from_str(&s).map_err(|_| ParseError::InvalidPositional(s))?
+        },)+))
+      }
+    }
+  };
+}
+
+impl_positionals!(A);
+impl_positionals!(A, B);
+impl_positionals!(A, B, C);
+impl_positionals!(A, B, C, D);
+impl_positionals!(A, B, C, D, E);
+impl_positionals!(A, B, C, D, E, F);
+
+/// Where positionals may be passed relative to flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PositionalPosition {
+  /// Positionals must come before all flags, such as `tool FILE -flag`.
+  Before,
+  /// Positionals must come after all flags, such as `tool -flag FILE`.
+  After,
+  /// Positionals and flags may be mixed.
+  #[default]
+  Anywhere,
+}
+
 /// Errors that can occur while parsing into flags.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ParseError {
@@ -177,9 +1356,85 @@ pub enum ParseError {
 
   /// Unknown flag was passed.
   UnknownFlag(String),
+
+  /// A flag which expects no value was passed more than once in strict mode.
+  DuplicateFlag(String),
+
+  /// Wrong number of positionals, specifies how many were expected and found.
+  PositionalCount(usize, usize),
+
+  /// A positional could not be parsed into its expected type.
+  InvalidPositional(String),
+
+  /// Required flags were not passed, listed in the order they were added.
+  MissingRequired(Vec<String>),
+
+  /// None of a group of flags where at least one is required were passed.
+  NoneProvided(Vec<String>),
+
+  /// More tokens were passed than the configured maximum.
+  TooManyArguments,
+
+  /// The first flag was greater than the second, which it must not exceed.
+  OrderViolation(String, String),
+
+  /// A positional was passed on the wrong side of a flag.
+  MisplacedPositional(String, PositionalPosition),
+
+  /// A macro was passed which expands to itself, directly or through other macros.
+  RecursiveMacro(String),
+
+  /// An error with a message describing what was being parsed.
+  Context(String, Box<ParseError>),
+}
+
+impl ParseError {
+  /// Wraps this error with a message describing what was being parsed.
+  pub fn with_context(self, ctx: &str) -> Self {
+    ParseError::Context(ctx.to_string(), Box::new(self))
+  }
+  /// Message shown to users for this error, empty if help was requested.
+  fn message(&self) -> String {
+    match self {
+      ParseError::HelpRequested => String::new(),
+      ParseError::ParseFromFailure(f, v) => format!("Invalid value for flag -{}: {}", f, v),
+      ParseError::UnknownFlag(f) => format!("flag provided but not defined: -{}", f),
+      ParseError::MissingValue(f) => format!("Missing value for flag: -{}", f),
+      ParseError::DuplicateFlag(f) => format!("flag provided more than once: -{}", f),
+      ParseError::PositionalCount(expected, found) => {
+        format!("Expected {} arguments, found {}", expected, found)
+      },
+      ParseError::InvalidPositional(v) => format!("Invalid argument \"{}\"", v),
+      ParseError::MissingRequired(flags) => {
+        format!("Missing required flags: -{}", flags.join(", -"))
+      },
+      ParseError::NoneProvided(flags) => {
+        format!("One of these flags must be passed: -{}", flags.join(", -"))
+      },
+      ParseError::TooManyArguments => String::from("Too many arguments"),
+      ParseError::OrderViolation(min, max) => {
+        format!("Flag -{} must not be greater than flag -{}", min, max)
+      },
+      ParseError::MisplacedPositional(v, PositionalPosition::Before) => {
+        format!("Argument \"{}\" must come before all flags", v)
+      },
+      ParseError::MisplacedPositional(v, _) => {
+        format!("Argument \"{}\" must come after all flags", v)
+      },
+      ParseError::RecursiveMacro(m) => format!("Macro -{} expands to itself", m),
+      ParseError::Context(ctx, e) => match e.message() {
+        msg if msg.is_empty() => msg,
+        msg => format!("{}: {}", ctx, msg),
+      },
+    }
+  }
 }
 
-use std::fmt;
 impl fmt::Display for ParseError {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{:?}", self) }
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ParseError::Context(..) => f.write_str(&self.message()),
+      e => write!(f, "{:?}", e),
+    }
+  }
 }
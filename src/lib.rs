@@ -2,6 +2,9 @@
 /// Defines how to make something into a flag,
 /// and how to parse arguments into a flag.
 pub mod flag;
+/// Value types which are useful as flags,
+/// beyond what can be parsed with `FromStr` out of the box.
+pub mod values;
 pub use flag::{FlagSet, Preset};
 #[cfg(test)]
 mod tests;
@@ -1,4 +1,23 @@
-use crate::flag::{FlagSet, ParseError, Preset};
+use std::{
+  cell::{Cell, RefCell},
+  collections::HashMap,
+  path::PathBuf,
+  rc::Rc,
+  time::Duration,
+};
+
+use crate::{
+  flag::{
+    FlagDiff, FlagSet, FlagSpec, Flaggable, ParseError, ParseStats, PositionalPosition, Preset,
+  },
+  values::{
+    AnyOf, Array, BaseAware, BitAccum, BoolList, ByteSize, Direction, DurationRange, DynFlag,
+    Email, ExpandedPath, HostPortList, IndexedMap, InputSource, Interpolated, LogFilter, LogLevel,
+    Money, OffsetTime, OneOf, Open, OpenEnum, PairList, PositiveDuration, PreciseFloat,
+    SignedByteSize, SortSpec, SortedUnique, Temperature, TimeOfDay, Trimmed, Undefined,
+    WritablePath,
+  },
+};
 
 #[test]
 fn test_basic() {
@@ -43,3 +62,1695 @@ fn cannot_parse() {
     .parse(vec!["--bool", "34"].into_iter().map(String::from))
     .is_err());
 }
+
+#[test]
+fn short_and_long() {
+  let mut fs = FlagSet::new();
+  let mut verbose = false;
+  let mut level: Option<i32> = None;
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  fs.add_named(Some('l'), "level", "level to use", &mut level);
+  assert!(fs.help_string().contains("-v, --verbose"));
+  assert!(fs.help_string().contains("-l, --level"));
+  assert!(fs
+    .parse(
+      vec!["-v", "--level", "2", "-l", "3"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+  assert!(verbose);
+  assert_eq!(level, Some(3));
+}
+
+#[test]
+fn short_shadowing() {
+  let mut verbose = false;
+  let mut v: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  fs.add("v", "version to use", &mut v);
+  assert!(fs
+    .parse(vec!["-v", "2"].into_iter().map(String::from))
+    .is_ok());
+  assert!(!verbose);
+  assert_eq!(v, Some("2".to_string()));
+}
+
+#[test]
+#[should_panic(expected = "short flag -h is already taken")]
+fn short_help_reserved() {
+  let mut host: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('h'), "host", "host to use", &mut host);
+}
+
+#[test]
+#[should_panic(expected = "short flag -v is already taken")]
+fn short_collision() {
+  let (mut verbose, mut version) = (false, false);
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  fs.add_named(Some('v'), "version", "print version", &mut version);
+}
+
+#[test]
+fn command_split() {
+  let mut fs = FlagSet::new();
+  let mut force = false;
+  fs.add("force", "force it", &mut force);
+  let (cmd, rem) = fs
+    .parse_with_command(
+      vec!["push", "-force", "origin", "main"]
+        .into_iter()
+        .map(String::from),
+    )
+    .unwrap();
+  assert_eq!(cmd.as_deref(), Some("push"));
+  assert_eq!(rem, vec!["origin", "main"]);
+  assert!(force);
+}
+
+#[test]
+fn ordering() {
+  let mut min: Option<u32> = None;
+  let mut max: Option<u32> = None;
+  let mut fs = FlagSet::new();
+  fs.add("min", "lower bound", &mut min);
+  fs.add("max", "upper bound", &mut max);
+  fs.add_ordering("min", "max");
+  assert_eq!(
+    fs.parse(
+      vec!["--min", "5", "--max", "3"]
+        .into_iter()
+        .map(String::from)
+    ),
+    Err(ParseError::OrderViolation(
+      "min".to_string(),
+      "max".to_string()
+    ))
+  );
+  assert!(fs
+    .parse(
+      vec!["--min", "3", "--max", "5"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+  assert_eq!(fs.value_of::<u32>("max"), Some(5));
+}
+
+#[test]
+fn help_interpolation() {
+  let mut port = Preset(8080);
+  let mut fs = FlagSet::new();
+  fs.add_default("port", "port to serve on, {default} or ${env}", &mut port);
+  fs.set_env("port", "EZFLAGS_TEST_PORT");
+  assert!(fs
+    .help_string()
+    .contains("port to serve on, 8080 or $EZFLAGS_TEST_PORT"));
+  std::env::set_var("EZFLAGS_TEST_PORT", "9000");
+  assert!(fs.parse(std::iter::empty()).is_ok());
+  assert_eq!(port.into_inner(), 9000);
+}
+
+#[test]
+fn env_bool() {
+  let mut verbose = false;
+  let mut fs = FlagSet::new();
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.set_env("verbose", "EZFLAGS_TEST_VERBOSE");
+  std::env::set_var("EZFLAGS_TEST_VERBOSE", "false");
+  assert!(fs.parse(std::iter::empty()).is_ok());
+  assert_eq!(fs.value_of::<String>("verbose"), None);
+  std::env::set_var("EZFLAGS_TEST_VERBOSE", "maybe");
+  assert_eq!(
+    fs.parse(std::iter::empty()),
+    Err(ParseError::ParseFromFailure(
+      "verbose".to_string(),
+      "invalid bool \"maybe\"".to_string()
+    ))
+  );
+  std::env::set_var("EZFLAGS_TEST_VERBOSE", "1");
+  assert!(fs.parse(std::iter::empty()).is_ok());
+  assert!(verbose);
+}
+
+#[test]
+fn input_source() {
+  let mut input: Option<InputSource> = None;
+  let mut output: Option<InputSource> = None;
+  let mut fs = FlagSet::new();
+  fs.add("in", "input", &mut input);
+  fs.add("out", "output", &mut output);
+  assert!(fs
+    .parse(vec!["-in", "-", "-out", "1"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(input, Some(InputSource::Stdin));
+  assert_eq!(output, Some(InputSource::Stdout));
+  assert_eq!(
+    "data/in.txt".parse(),
+    Ok(InputSource::Path("data/in.txt".into()))
+  );
+}
+
+#[test]
+fn max_tokens() {
+  let mut fs = FlagSet::new().with_max_tokens(3);
+  assert!(fs
+    .parse(vec!["a", "b", "c"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["a", "b", "c", "d"].into_iter().map(String::from)),
+    Err(ParseError::TooManyArguments)
+  );
+}
+
+#[test]
+fn bit_accum() {
+  let mut perms = BitAccum::new(&[("read", 0), ("write", 1), ("exec", 2)]);
+  let mut fs = FlagSet::new();
+  fs.add("perm", "permission to grant", &mut perms);
+  assert!(fs
+    .parse(
+      vec!["-perm", "read", "-perm", "exec"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+  assert!(fs
+    .parse(vec!["-perm", "all"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(perms.bits, 0b101);
+}
+
+#[test]
+fn owned_names() {
+  let mut level: Option<u8> = None;
+  let plugin = "compress";
+  let mut fs = FlagSet::new();
+  fs.add_owned(
+    format!("{}-level", plugin),
+    format!("level for {}", plugin),
+    &mut level,
+  );
+  assert!(fs.help_string().contains("-compress-level"));
+  assert!(fs
+    .parse(vec!["--compress-level", "9"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(level, Some(9));
+}
+
+#[test]
+fn host_port_list() {
+  let mut nodes = HostPortList::default();
+  let mut fs = FlagSet::new();
+  fs.add("nodes", "cluster nodes", &mut nodes);
+  let parse =
+    |fs: &mut FlagSet, v: &str| fs.parse(vec!["-nodes".to_string(), v.to_string()].into_iter());
+  assert!(parse(&mut fs, "host1:9092,[::1]:9093").is_ok());
+  assert_eq!(
+    parse(&mut fs, "host1:9092,host2"),
+    Err(ParseError::ParseFromFailure(
+      "nodes".to_string(),
+      "missing port in \"host2\"".to_string()
+    ))
+  );
+  assert!(parse(&mut fs, "host1:http").is_err());
+  assert_eq!(
+    nodes.0,
+    vec![("host1".to_string(), 9092), ("::1".to_string(), 9093)]
+  );
+}
+
+#[test]
+fn markdown_help() {
+  let mut port = Preset(8080);
+  let mut verbose = false;
+  let mut fs = FlagSet::new();
+  fs.add_default("port", "port to serve on", &mut port);
+  fs.set_metavar("port", "PORT");
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  let md = fs.help_markdown();
+  assert!(md.starts_with("| Flag | Value | Description | Default |\n"));
+  assert!(md.contains("| `-port` | `PORT` | port to serve on | `8080` |\n"));
+  assert!(md.contains("| `-v, --verbose` |  | be verbose |  |\n"));
+  let mut sep = Preset("|".to_string());
+  let mut fs = FlagSet::new();
+  fs.add_default("sep", "column separator", &mut sep);
+  fs.set_metavar("sep", "CHAR|STR");
+  assert!(fs
+    .help_markdown()
+    .contains("| `-sep` | `CHAR\\|STR` | column separator | `\\|` |\n"));
+}
+
+#[test]
+fn strict_bool() {
+  let mut on = false;
+  let mut fs = FlagSet::new().strict_bool(true);
+  fs.add("on", "turn on", &mut on);
+  assert_eq!(
+    fs.parse(vec!["-on", "--on"].into_iter().map(String::from)),
+    Err(ParseError::DuplicateFlag("on".to_string()))
+  );
+  fs.reset();
+  assert!(fs.parse(vec!["-on"].into_iter().map(String::from)).is_ok());
+}
+
+#[test]
+fn typed_positionals() {
+  let mut fs = FlagSet::new();
+  let args = |v: &[&str]| {
+    v.iter()
+      .map(|s| s.to_string())
+      .collect::<Vec<_>>()
+      .into_iter()
+  };
+  let (file, port): (PathBuf, u16) = fs.expect_positionals(args(&["file.txt", "8080"])).unwrap();
+  assert_eq!(file, PathBuf::from("file.txt"));
+  assert_eq!(port, 8080);
+  assert_eq!(
+    fs.expect_positionals::<(PathBuf, u16), _>(args(&["file.txt"])),
+    Err(ParseError::PositionalCount(2, 1))
+  );
+  assert_eq!(
+    fs.expect_positionals::<(PathBuf, u16), _>(args(&["file.txt", "http"])),
+    Err(ParseError::InvalidPositional("http".to_string()))
+  );
+}
+
+#[test]
+fn signed_byte_size() {
+  let mut grow: Option<SignedByteSize> = None;
+  let mut shrink: Option<SignedByteSize> = None;
+  let mut fs = FlagSet::new();
+  fs.add("grow", "bytes to grow by", &mut grow);
+  fs.add("shrink", "bytes to shrink by", &mut shrink);
+  assert!(fs
+    .parse(
+      vec!["--grow", "+100M", "--shrink", "-50M"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+  assert_eq!(grow, Some(SignedByteSize(100 << 20)));
+  assert_eq!(shrink, Some(SignedByteSize(-50 << 20)));
+  assert_eq!("4096".parse(), Ok(SignedByteSize(4096)));
+  assert_eq!("2K".parse(), Ok(ByteSize(2048)));
+  assert!("3Q".parse::<SignedByteSize>().is_err());
+}
+
+#[test]
+fn command_help() {
+  let mut force = false;
+  let mut fs = FlagSet::new();
+  fs.add("force", "force it", &mut force);
+  fs.add_command("push", "push commits");
+  fs.add_command("pull", "pull commits");
+  let help = fs.help_string();
+  assert!(help.contains("  -force\n\t force it\n"));
+  assert!(help.contains("Commands:\n  push\n\t push commits\n  pull\n\t pull commits\n"));
+}
+
+#[test]
+fn precise_float() {
+  let mut rate = PreciseFloat::new(4);
+  assert!(rate.parse_from("12.5").is_ok());
+  assert_eq!(rate.value, Some(12.5));
+  assert!(rate.parse_from("1.5e3").is_ok());
+  assert_eq!(rate.value, Some(1500.0));
+  assert!(rate.parse_from("0.000123").is_ok());
+  assert!(rate.parse_from("3.14159").is_err());
+  assert_eq!(rate.value, Some(0.000123));
+}
+
+#[test]
+fn preprocessor() {
+  let mut jobs: Option<u32> = None;
+  let rename = |args: Vec<String>| {
+    let upgrade = |a: String| {
+      if a == "-num_jobs" {
+        String::from("-jobs")
+      } else {
+        a
+      }
+    };
+    args.into_iter().map(upgrade).collect()
+  };
+  let mut fs = FlagSet::new().with_preprocessor(&rename);
+  fs.add("jobs", "number of jobs", &mut jobs);
+  assert!(fs
+    .parse(vec!["-num_jobs", "4"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(jobs, Some(4));
+}
+
+#[test]
+fn offset_time() {
+  let at = |hour, minute| TimeOfDay {
+    hour,
+    minute,
+    second: 0,
+  };
+  assert_eq!(
+    "14:30+02:00".parse(),
+    Ok(OffsetTime {
+      time: at(14, 30),
+      offset_minutes: 120,
+    })
+  );
+  assert_eq!(
+    "09:15".parse(),
+    Ok(OffsetTime {
+      time: at(9, 15),
+      offset_minutes: 0,
+    })
+  );
+  assert_eq!(
+    "22:00-05:30"
+      .parse::<OffsetTime>()
+      .map(|t| t.offset_minutes),
+    Ok(-330)
+  );
+  assert!("14:30+15:00".parse::<OffsetTime>().is_err());
+  assert!("14:30+2".parse::<OffsetTime>().is_err());
+  assert!("25:00".parse::<TimeOfDay>().is_err());
+}
+
+#[test]
+fn multiple_parses() {
+  let mut min: Option<i32> = None;
+  let mut max: Option<i32> = None;
+  let mut fs = FlagSet::new().strict_bool(true);
+  fs.add("min", "lower bound", &mut min);
+  fs.add("max", "upper bound", &mut max);
+  fs.add_ordering("min", "max");
+  fs.set_env("min", "EZFLAGS_TEST_MIN");
+  std::env::set_var("EZFLAGS_TEST_MIN", "100");
+  assert!(fs
+    .parse(vec!["-min", "1"].into_iter().map(String::from))
+    .is_ok());
+  assert!(fs
+    .parse(vec!["-max", "7"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(fs.value_of::<i32>("min"), Some(1));
+  assert_eq!(
+    fs.parse(vec!["-min", "9"].into_iter().map(String::from)),
+    Err(ParseError::OrderViolation(
+      "min".to_string(),
+      "max".to_string()
+    ))
+  );
+  fs.reset();
+  assert_eq!(fs.value_of::<i32>("max"), None);
+  assert!(fs
+    .parse(vec!["-max", "200"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(min, Some(100));
+  assert_eq!(max, Some(200));
+}
+
+#[test]
+fn staged_parse() {
+  let mut verbose = false;
+  let mut tags: Vec<String> = vec![];
+  let mut fs = FlagSet::new().strict_bool(true);
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.add("tag", "tags to add", &mut tags);
+  fs.set_env("verbose", "EZFLAGS_TEST_STAGED_VERBOSE");
+  fs.set_env("tag", "EZFLAGS_TEST_STAGED_TAG");
+  std::env::set_var("EZFLAGS_TEST_STAGED_VERBOSE", "1");
+  std::env::set_var("EZFLAGS_TEST_STAGED_TAG", "e");
+  assert_eq!(fs.parse_partial(std::iter::empty()), Ok(vec![]));
+  assert_eq!(fs.value_of::<String>("tag"), None);
+  let args = vec!["-verbose", "-tag", "c"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(verbose);
+  assert_eq!(tags, vec!["c".to_string()]);
+}
+
+#[test]
+fn bool_list() {
+  let mut features = BoolList::default();
+  assert!(features.parse_from("on,off,true,0").is_ok());
+  assert_eq!(features.0, vec![true, false, true, false]);
+  assert_eq!(
+    features.parse_from("on,yes"),
+    Err("invalid bool \"yes\" at position 1".to_string())
+  );
+}
+
+#[test]
+fn completion_spec() {
+  let mut verbose = false;
+  let mut out: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.add("out", "output file", &mut out);
+  assert_eq!(
+    fs.completion_spec(),
+    vec![
+      ("out".to_string(), true, "output file"),
+      ("verbose".to_string(), false, "be verbose"),
+    ]
+  );
+}
+
+#[test]
+fn zsh_completion() {
+  let mut verbose = false;
+  let mut out: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  fs.add("out", "output file [default: stdout]", &mut out);
+  let zsh = fs.zsh_completion("tool");
+  assert!(zsh.starts_with("#compdef tool\n"));
+  assert!(zsh.contains("'-out[output file \\[default\\: stdout\\]]:value:'"));
+  assert!(zsh.contains("'(-v --verbose)'{-v,--verbose}'[be verbose]'"));
+  assert!(zsh.ends_with("_tool \"$@\"\n"));
+  let mut addr: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add(
+    "addr",
+    "address to bind,\n  such as localhost:80",
+    &mut addr,
+  );
+  fs.set_metavar("addr", "HOST:PORT");
+  assert!(fs
+    .zsh_completion("tool")
+    .contains("'-addr[address to bind, such as localhost\\:80]:HOST\\:PORT:'"));
+}
+
+#[test]
+fn prefix_catchall() {
+  let mut forwarded = vec![];
+  let mut fs = FlagSet::new();
+  fs.add_prefix_catchall("--cc-", &mut forwarded);
+  let args = vec!["--cc-O2", "--cc-std=c11", "--cc-include", "a.h", "main.c"];
+  assert_eq!(
+    fs.parse(args.into_iter().map(String::from)),
+    Ok(vec!["main.c".to_string()])
+  );
+  assert!(fs
+    .parse(vec!["--ld-x"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(
+    forwarded,
+    vec![
+      ("O2".to_string(), None),
+      ("std".to_string(), Some("c11".to_string())),
+      ("include".to_string(), Some("a.h".to_string())),
+    ]
+  );
+}
+
+#[test]
+fn email() {
+  let mut to: Vec<Email> = vec![];
+  let mut fs = FlagSet::new();
+  fs.add("to", "recipient", &mut to);
+  let args = vec!["-to", "user@example.com", "-to", "ops@example.org"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["-to", "example.com"].into_iter().map(String::from))
+    .is_err());
+  assert!(fs
+    .parse(vec!["-to", "@example.com"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(to.len(), 2);
+  assert_eq!(to[0].local(), "user");
+  assert_eq!(to[1].domain(), "example.org");
+}
+
+#[test]
+fn diff() {
+  let (mut a, mut b, mut b2, mut c) = (false, false, false, false);
+  let mut old = FlagSet::new();
+  old.add("a", "flag a", &mut a);
+  old.add("b", "flag b", &mut b);
+  let mut new = FlagSet::new();
+  new.add("b", "flag b, changed", &mut b2);
+  new.add("c", "flag c", &mut c);
+  assert_eq!(
+    old.diff(&new),
+    FlagDiff {
+      added: vec!["c".to_string()],
+      removed: vec!["a".to_string()],
+      help_changed: vec!["b".to_string()],
+    }
+  );
+}
+
+#[test]
+fn expanded_path() {
+  let home = std::env::var("HOME").unwrap();
+  assert_eq!(
+    "~/projects".parse(),
+    Ok(ExpandedPath(PathBuf::from(home).join("projects")))
+  );
+  assert_eq!("/etc/hosts".parse(), Ok(ExpandedPath("/etc/hosts".into())));
+  assert_eq!("~user/x".parse(), Ok(ExpandedPath("~user/x".into())));
+}
+
+#[test]
+fn error_with_help() {
+  let mut on = false;
+  let mut fs = FlagSet::new();
+  fs.add("on", "turn on", &mut on);
+  let (err, help) = fs
+    .parse_or_help(vec!["-off"].into_iter().map(String::from))
+    .unwrap_err();
+  assert_eq!(err, ParseError::UnknownFlag("off".to_string()));
+  assert_eq!(help, fs.help_string());
+  assert!(help.contains("turn on"));
+}
+
+#[test]
+fn one_of() {
+  let mut retries = OneOf::new(&[0, 1, 3, 5]);
+  assert!(retries.parse_from("3").is_ok());
+  assert_eq!(
+    retries.parse_from("2"),
+    Err("\"2\" is not one of [0, 1, 3, 5]".to_string())
+  );
+  assert_eq!(retries.value, Some(3));
+}
+
+#[test]
+fn dispatch() {
+  let mut seen = vec![];
+  let mut verbose = false;
+  let mut level: Option<u8> = None;
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  fs.add("level", "level to use", &mut level);
+  let args = vec!["-level", "2", "-v", "file", "-level", "3"];
+  let rem = fs.parse_dispatch(args.into_iter().map(String::from), |name, value| {
+    seen.push((name.to_string(), value.map(String::from)))
+  });
+  assert_eq!(rem, Ok(vec!["file".to_string()]));
+  assert_eq!(
+    seen,
+    vec![
+      ("level".to_string(), Some("2".to_string())),
+      ("verbose".to_string(), None),
+      ("level".to_string(), Some("3".to_string())),
+    ]
+  );
+  assert_eq!(level, Some(3));
+}
+
+#[test]
+fn trimmed() {
+  let mut strict: Option<i32> = None;
+  let mut num: Trimmed<Option<i32>> = Trimmed(None);
+  let mut fs = FlagSet::new();
+  fs.add("strict", "untrimmed num", &mut strict);
+  fs.add("num", "trimmed num", &mut num);
+  assert!(fs
+    .parse(vec!["-num", " 3 "].into_iter().map(String::from))
+    .is_ok());
+  assert!(fs
+    .parse(vec!["-strict", " 3 "].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(num.0, Some(3));
+}
+
+#[test]
+fn lenient() {
+  let mut verbose = false;
+  let mut level: Option<u8> = None;
+  let mut fs = FlagSet::new();
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.add("level", "level to use", &mut level);
+  let args = vec!["-x", "-level", "2", "a", "--yes", "-verbose", "b"];
+  assert_eq!(
+    fs.parse_lenient(args.into_iter().map(String::from)),
+    Ok((
+      vec!["a".to_string(), "b".to_string()],
+      vec!["x".to_string(), "yes".to_string()]
+    ))
+  );
+  assert!(verbose);
+  assert_eq!(level, Some(2));
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn uuid() {
+  let mut id = uuid::Uuid::nil();
+  let mut fs = FlagSet::new();
+  fs.add("id", "id to look up", &mut id);
+  let args = vec!["-id", "550e8400-e29b-41d4-a716-446655440000"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["-id", "550e8400-e29b"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(id.to_string(), "550e8400-e29b-41d4-a716-446655440000");
+}
+
+#[cfg(feature = "glob")]
+#[test]
+fn glob_list() {
+  let mut include = crate::values::GlobList::default();
+  let mut fs = FlagSet::new();
+  fs.add("include", "files to include", &mut include);
+  let args = vec![
+    "-include",
+    "*.c",
+    "-include-",
+    "-include",
+    "*.rs",
+    "-include",
+    "src/**/*.md",
+  ];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["-include", "src/[a"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(include.0.len(), 2);
+  assert!(include.matches("lib.rs"));
+  assert!(!include.matches("main.c"));
+  assert!(include.matches("src/docs/readme.md"));
+  assert!(!include.matches("Cargo.toml"));
+}
+
+#[test]
+fn macros() {
+  let mut threads: Option<u32> = None;
+  let mut cache: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add("threads", "threads to use", &mut threads);
+  fs.add("cache", "cache mode", &mut cache);
+  fs.add_macro("fast", "go fast", &["--threads", "8", "--cache", "on"]);
+  assert!(fs.help_string().contains("go fast"));
+  assert_eq!(
+    fs.parse(vec!["-fast", "file"].into_iter().map(String::from)),
+    Ok(vec!["file".to_string()])
+  );
+  fs.add_macro("loop", "never ends", &["--threads", "2", "--again"]);
+  fs.add_macro("again", "loops back", &["-loop"]);
+  assert_eq!(
+    fs.parse(vec!["-loop"].into_iter().map(String::from)),
+    Err(ParseError::RecursiveMacro("loop".to_string()))
+  );
+  assert_eq!(threads, Some(8));
+  assert_eq!(cache.as_deref(), Some("on"));
+}
+
+#[test]
+fn duration_range() {
+  assert_eq!(
+    "100ms..2s".parse(),
+    Ok(DurationRange {
+      min: Duration::from_millis(100),
+      max: Duration::from_secs(2),
+    })
+  );
+  assert!("2s..100ms".parse::<DurationRange>().is_err());
+  assert!("100ms..2x".parse::<DurationRange>().is_err());
+  assert!("100ms".parse::<DurationRange>().is_err());
+  let mut backoff: Option<DurationRange> = None;
+  let mut fs = FlagSet::new();
+  fs.add("backoff", "retry backoff", &mut backoff);
+  assert_eq!(
+    fs.parse(vec!["-backoff", "2s..100ms"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "backoff".to_string(),
+      "range \"2s..100ms\" has its minimum above its maximum".to_string()
+    ))
+  );
+}
+
+#[test]
+fn env_in_help() {
+  let mut port: Option<u16> = None;
+  let mut max_conns: Option<u16> = None;
+  let mut verbose = false;
+  let mut fs = FlagSet::new();
+  fs.add("port", "server port", &mut port);
+  fs.set_env("port", "MYAPP_PORT");
+  fs.add("verbose", "be verbose", &mut verbose);
+  assert!(fs
+    .help_string()
+    .contains("\t server port [env: MYAPP_PORT]\n"));
+  assert!(fs.help_string().contains("\t be verbose\n"));
+  let mut quiet = false;
+  let mut fs = FlagSet::new().with_env_prefix("EZFLAGS_TEST");
+  fs.add("max-conns", "connection limit", &mut max_conns);
+  fs.add("quiet", "be quiet", &mut quiet);
+  assert!(fs
+    .help_string()
+    .contains("\t connection limit [env: EZFLAGS_TEST_MAX_CONNS]\n"));
+  std::env::set_var("EZFLAGS_TEST_MAX_CONNS", "12");
+  std::env::set_var("EZFLAGS_TEST_QUIET", "0");
+  assert!(fs.parse(std::iter::empty()).is_ok());
+  assert_eq!(max_conns, Some(12));
+  assert!(!quiet);
+}
+
+#[test]
+fn array() {
+  let mut matrix: Option<Array<f64, 4>> = None;
+  let mut fs = FlagSet::new();
+  fs.add("matrix", "2x2 matrix", &mut matrix);
+  assert!(fs
+    .parse(vec!["-matrix", "1,2,3,4"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["-matrix", "1,2,3"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "matrix".to_string(),
+      "\"1,2,3\": expected 4 elements, found 3".to_string()
+    ))
+  );
+  assert_eq!(matrix, Some(Array([1., 2., 3., 4.])));
+  assert_eq!(
+    "1,2,3".parse::<Array<f64, 4>>(),
+    Err("expected 4 elements, found 3".to_string())
+  );
+  assert!("1,2,3,4,5".parse::<Array<f64, 4>>().is_err());
+}
+
+#[test]
+fn missing_required() {
+  let (mut zeta, mut alpha, mut mid): (Option<u8>, Option<u8>, Option<u8>) = (None, None, None);
+  let mut fs = FlagSet::new();
+  fs.add("zeta", "last letter", &mut zeta);
+  fs.add("alpha", "first letter", &mut alpha);
+  fs.add("mid", "middle letter", &mut mid);
+  fs.set_required("alpha");
+  fs.set_required("zeta");
+  fs.set_required("mid");
+  assert_eq!(
+    fs.parse(vec!["-mid", "1"].into_iter().map(String::from)),
+    Err(ParseError::MissingRequired(vec![
+      "zeta".to_string(),
+      "alpha".to_string()
+    ]))
+  );
+  assert!(fs
+    .parse(
+      vec!["-alpha", "1", "-zeta", "2"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+}
+
+#[test]
+fn required_in_later_stage() {
+  let (mut x, mut y): (Option<u8>, Option<u8>) = (None, None);
+  let mut stdin = false;
+  let mut fs = FlagSet::new();
+  fs.add("x", "first stage", &mut x);
+  fs.add("y", "second stage", &mut y);
+  fs.add("stdin", "read from stdin", &mut stdin);
+  fs.set_required("y");
+  fs.require_any(&["y", "stdin"]);
+  assert!(fs
+    .parse_partial(vec!["-x", "1"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.complete(),
+    Err(ParseError::MissingRequired(vec!["y".to_string()]))
+  );
+  assert!(fs
+    .parse_partial(vec!["-x", "1"].into_iter().map(String::from))
+    .is_ok());
+  assert!(fs
+    .parse(vec!["-y", "2"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!((x, y), (Some(1), Some(2)));
+}
+
+#[test]
+fn temperature() {
+  let kelvin = |s: &str| {
+    s.parse::<Temperature>()
+      .map(|t| (t.kelvin() * 100.).round() / 100.)
+  };
+  assert_eq!(kelvin("22C"), Ok(295.15));
+  assert_eq!(kelvin("72F"), Ok(295.37));
+  assert_eq!(kelvin("295K"), Ok(295.));
+  assert_eq!(kelvin("-10°C"), Ok(263.15));
+  assert!(kelvin("22").is_err());
+  assert!(kelvin("22X").is_err());
+  assert!(kelvin("-300C").is_err());
+  let mut target: Option<Temperature> = None;
+  let mut fs = FlagSet::new();
+  fs.add("target", "target temperature", &mut target);
+  assert_eq!(
+    fs.parse(vec!["-target", "22"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "target".to_string(),
+      "invalid temperature \"22\", expected a C, F or K suffix".to_string()
+    ))
+  );
+}
+
+#[test]
+fn dynamic_choice() {
+  let choices: Vec<String> = (1..=7).map(|i| format!("db{}", i)).collect();
+  let mut db: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add_dynamic_choice("db", "database to use", choices, &mut db);
+  assert!(fs
+    .parse(vec!["-db", "db3"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["-db", "db9"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "db".to_string(),
+      "\"db9\" is not one of db1, db2, db3, db4, db5, and 2 more".to_string()
+    ))
+  );
+  assert_eq!(db.as_deref(), Some("db3"));
+}
+
+#[test]
+fn run_status() {
+  let mut out = vec![];
+  let mut on = false;
+  let mut jobs: Option<u8> = None;
+  let mut fs = FlagSet::new().with_writer(&mut out);
+  fs.add("on", "turn on", &mut on);
+  fs.add("jobs", "number of jobs", &mut jobs);
+  assert_eq!(
+    fs.run_from(vec!["-on", "x"].into_iter().map(String::from)),
+    (vec!["x".to_string()], 0)
+  );
+  assert_eq!(
+    fs.run_from(vec!["-off"].into_iter().map(String::from)),
+    (vec![], 1)
+  );
+  assert_eq!(
+    fs.run_from(vec!["-h"].into_iter().map(String::from)),
+    (vec![], 0)
+  );
+  assert_eq!(
+    fs.run_from(vec!["-jobs", "many"].into_iter().map(String::from)),
+    (vec![], 1)
+  );
+  let out = String::from_utf8(out).unwrap();
+  assert!(out.starts_with("flag provided but not defined: -off\nUsage:\n"));
+  assert!(out
+    .contains("\nInvalid value for flag -jobs: \"many\": invalid digit found in string\nUsage:\n"));
+}
+
+#[test]
+fn label_map() {
+  let mut labels: HashMap<String, String> = HashMap::new();
+  let mut fs = FlagSet::new();
+  fs.add("label", "label to attach", &mut labels);
+  let args = vec![
+    "--label",
+    "env=staging",
+    "--label",
+    "tier=web",
+    "--label",
+    "env=prod",
+  ];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["--label", "oops"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(labels.len(), 2);
+  assert_eq!(labels["env"], "prod");
+  assert_eq!(labels["tier"], "web");
+}
+
+#[test]
+fn base_aware() {
+  let base = Rc::new(Cell::new(10));
+  let mut base_flag = base.clone();
+  let mut value = BaseAware::new(base);
+  let mut fs = FlagSet::new();
+  fs.add("base", "base of value", &mut base_flag);
+  fs.add("value", "value to convert", &mut value);
+  assert!(fs
+    .parse(
+      vec!["--base", "16", "--value", "ff"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+  assert!(fs
+    .parse(vec!["--base", "0x"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(value.value, Some(255));
+}
+
+#[test]
+fn is_registered() {
+  let mut verbose = false;
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  assert!(fs.is_registered("verbose"));
+  assert!(fs.is_registered("--verbose"));
+  assert!(fs.is_registered("-v"));
+  assert!(!fs.is_registered("quiet"));
+  assert!(!fs.is_registered("--quiet"));
+}
+
+#[test]
+fn positive_duration() {
+  let mut timeout: Option<PositiveDuration> = None;
+  let mut fs = FlagSet::new();
+  fs.add("timeout", "request timeout", &mut timeout);
+  assert!(fs
+    .parse(vec!["-timeout", "30s"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["-timeout", "0s"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "timeout".to_string(),
+      "duration must be positive, got \"0s\"".to_string()
+    ))
+  );
+  assert_eq!(timeout, Some(PositiveDuration(Duration::from_secs(30))));
+  assert_eq!(
+    "0s".parse::<PositiveDuration>(),
+    Err("duration must be positive, got \"0s\"".to_string())
+  );
+  assert!("-5s".parse::<PositiveDuration>().is_err());
+}
+
+#[test]
+fn lone_dash() {
+  let mut fs = FlagSet::new();
+  assert_eq!(
+    fs.parse(vec!["a", "-", "b"].into_iter().map(String::from)),
+    Ok(vec!["a".to_string(), "-".to_string(), "b".to_string()])
+  );
+}
+
+#[test]
+fn pair_list() {
+  let mut map: PairList<String, i32> = PairList::default();
+  assert!(map.parse_from("a:1,b:2,c:3").is_ok());
+  assert_eq!(
+    map.0,
+    vec![
+      ("a".to_string(), 1),
+      ("b".to_string(), 2),
+      ("c".to_string(), 3)
+    ]
+  );
+  assert_eq!(
+    map.parse_from("a:1,b"),
+    Err("missing ':' in pair \"b\"".to_string())
+  );
+  assert_eq!(
+    map.parse_from("a:1,b:two"),
+    Err("invalid pair \"b:two\"".to_string())
+  );
+}
+
+#[test]
+fn error_context() {
+  let err = ParseError::UnknownFlag("x".to_string()).with_context("while parsing server config");
+  assert_eq!(
+    err.to_string(),
+    "while parsing server config: flag provided but not defined: -x"
+  );
+}
+
+#[test]
+fn open_enum() {
+  #[derive(Clone, Debug, PartialEq)]
+  enum Codec {
+    Gzip,
+    Zstd,
+  }
+  let mut codec = OpenEnum::new(&[("gzip", Codec::Gzip), ("zstd", Codec::Zstd)]);
+  assert!(codec.parse_from("zstd").is_ok());
+  assert_eq!(codec.value, Some(Open::Known(Codec::Zstd)));
+  assert!(codec.parse_from("brotli").is_ok());
+  assert_eq!(codec.value, Some(Open::Other("brotli".to_string())));
+}
+
+#[test]
+fn truncated_default() {
+  let mut motd = Preset(String::from("a very long message of the day"));
+  let mut fs = FlagSet::new().with_max_help_value(10);
+  fs.add_default("motd", "message shown on login ({default})", &mut motd);
+  assert!(fs
+    .help_string()
+    .contains("message shown on login (a very lon…)"));
+  assert!(fs.help_markdown().contains("| `a very lon…` |"));
+}
+
+#[test]
+fn writable_path() {
+  let dir = std::env::temp_dir().join(format!("ezflags-writable-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let new_file = dir.join("out.txt");
+  let existing = dir.join("existing.txt");
+  std::fs::write(&existing, "kept").unwrap();
+  let parse = |p: &std::path::Path| p.to_str().unwrap().parse::<WritablePath>();
+  assert_eq!(parse(&new_file), Ok(WritablePath(new_file.clone())));
+  assert!(!new_file.exists());
+  assert_eq!(parse(&existing), Ok(WritablePath(existing.clone())));
+  assert_eq!(std::fs::read_to_string(&existing).unwrap(), "kept");
+  assert!(parse(&dir).is_err());
+  assert!(parse(&dir.join("missing").join("out.txt")).is_err());
+  assert!(parse(&existing.join("out.txt")).is_err());
+  let missing = dir.join("missing").join("out.txt");
+  let missing = missing.to_str().unwrap();
+  let mut out: Option<WritablePath> = None;
+  let mut fs = FlagSet::new();
+  fs.add("out", "file to write", &mut out);
+  assert_eq!(
+    fs.parse(vec!["-out", missing].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "out".to_string(),
+      format!("cannot write to {}", missing)
+    ))
+  );
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parse_stats() {
+  let mut verbose = false;
+  let mut level: Option<u8> = None;
+  let mut fs = FlagSet::new();
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.add("level", "level to use", &mut level);
+  let args = vec!["-verbose", "-level", "3", "a", "b"];
+  assert_eq!(
+    fs.parse_with_stats(args.into_iter().map(String::from)),
+    Ok((
+      vec!["a".to_string(), "b".to_string()],
+      ParseStats {
+        tokens: 5,
+        lookups: 2,
+      }
+    ))
+  );
+}
+
+#[test]
+fn registration_order() {
+  let (mut zeta, mut alpha) = (false, false);
+  let mut mid: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add("zeta", "last letter", &mut zeta);
+  fs.add("mid", "in between", &mut mid);
+  fs.add_macro("fast", "go fast", &["-zeta"]);
+  fs.add("alpha", "first letter", &mut alpha);
+  assert_eq!(fs.flag_names(), vec!["zeta", "mid", "fast", "alpha"]);
+  assert_eq!(
+    fs.help_string(),
+    "Usage:\n  -zeta\n\t last letter\n  -mid\n\t in between\n  -fast\n\t go fast\n  \
+     -alpha\n\t first letter\n"
+  );
+  let md = fs.help_markdown();
+  assert!(md.find("`-zeta`") < md.find("`-alpha`"));
+}
+
+#[test]
+fn interpolated() {
+  std::env::set_var("EZFLAGS_TEST_HOME", "/home/user");
+  let mut path: Interpolated<Option<String>> = Interpolated::new(None, Undefined::Error);
+  let mut kept: Interpolated<Option<String>> = Interpolated::new(None, Undefined::Keep);
+  let mut fs = FlagSet::new();
+  fs.add("path", "config path", &mut path);
+  fs.add("kept", "path left as is", &mut kept);
+  let args = vec![
+    "-path",
+    "${EZFLAGS_TEST_HOME}/cfg",
+    "-kept",
+    "${EZFLAGS_TEST_UNSET}/cfg",
+  ];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert_eq!(
+    fs.parse(
+      vec!["-path", "${EZFLAGS_TEST_UNSET}"]
+        .into_iter()
+        .map(String::from)
+    ),
+    Err(ParseError::ParseFromFailure(
+      "path".to_string(),
+      "${EZFLAGS_TEST_UNSET} is not set".to_string()
+    ))
+  );
+  assert_eq!(path.value, Some("/home/user/cfg".to_string()));
+  assert_eq!(kept.value, Some("${EZFLAGS_TEST_UNSET}/cfg".to_string()));
+}
+
+#[test]
+fn indexed_map() {
+  let mut items = IndexedMap::default();
+  let mut fs = FlagSet::new();
+  fs.add_indexed("item", "items to add", &mut items);
+  let args = vec![
+    "--item.1.name",
+    "bar",
+    "--item.0.name",
+    "foo",
+    "--item.0.age",
+    "3",
+  ];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["--item.x.name", "baz"].into_iter().map(String::from))
+    .is_err());
+  for index in ["1024", "18446744073709551615"] {
+    let key = format!("--item.{}.name", index);
+    assert_eq!(
+      fs.parse(vec![key, "baz".to_string()].into_iter()),
+      Err(ParseError::ParseFromFailure(
+        format!("item.{}.name", index),
+        format!("index {} is too large", index)
+      ))
+    );
+  }
+  assert_eq!(items.0.len(), 2);
+  assert_eq!(items.0[0]["name"], "foo");
+  assert_eq!(items.0[0]["age"], "3");
+  assert_eq!(items.0[1]["name"], "bar");
+}
+
+#[test]
+fn parse_until() {
+  let mut verbose = false;
+  let mut force = false;
+  let mut global = FlagSet::new();
+  global.add("verbose", "be verbose", &mut verbose);
+  let args = vec!["-verbose", "push", "-force", "origin"];
+  let tail = global
+    .parse_until(args.into_iter().map(String::from))
+    .unwrap();
+  let mut cmd = FlagSet::new();
+  cmd.add("force", "force push", &mut force);
+  assert_eq!(
+    cmd.parse(tail),
+    Ok(vec!["push".to_string(), "origin".to_string()])
+  );
+  let args = vec!["-verbose", "--", "-force"];
+  let tail = global
+    .parse_until(args.into_iter().map(String::from))
+    .unwrap();
+  assert_eq!(tail.collect::<Vec<_>>(), vec!["-force".to_string()]);
+  assert!(force);
+}
+
+#[test]
+fn money() {
+  let mut price: Option<Money> = None;
+  let mut fee: Option<Money> = None;
+  let mut fs = FlagSet::new();
+  fs.add("price", "price to charge", &mut price);
+  fs.add("fee", "fee to add", &mut fee);
+  let args = vec!["-price", "$12.99", "-fee", "12.5EUR"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert_eq!(
+    fs.parse(vec!["-price", "12.999USD"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "price".to_string(),
+      "\"12.999USD\": invalid amount \"12.999\"".to_string()
+    ))
+  );
+  assert!(fs
+    .parse(vec!["-price", "12.99"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(
+    price,
+    Some(Money {
+      minor: 1299,
+      currency: "USD".to_string()
+    })
+  );
+  assert_eq!(fee.map(|f| f.minor), Some(1250));
+  let minor = |s: &str| s.parse::<Money>().map(|m| m.minor);
+  assert_eq!(minor("¥500"), Ok(500));
+  assert_eq!(minor("1.5KWD"), Ok(1500));
+  assert!(minor("¥5.00").is_err());
+}
+
+#[test]
+fn positional_position() {
+  let mut verbose = false;
+  let mut fs = FlagSet::new().positional_position(PositionalPosition::Before);
+  fs.add("verbose", "be verbose", &mut verbose);
+  assert!(fs
+    .parse(vec!["file", "-verbose"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["-verbose", "file"].into_iter().map(String::from)),
+    Err(ParseError::MisplacedPositional(
+      "file".to_string(),
+      PositionalPosition::Before
+    ))
+  );
+  let mut fs = FlagSet::new().positional_position(PositionalPosition::After);
+  fs.add("verbose", "be verbose", &mut verbose);
+  assert!(fs
+    .parse(vec!["-verbose", "file"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["file", "-verbose"].into_iter().map(String::from)),
+    Err(ParseError::MisplacedPositional(
+      "file".to_string(),
+      PositionalPosition::After
+    ))
+  );
+  let mut fs = FlagSet::new().positional_position(PositionalPosition::Anywhere);
+  fs.add("verbose", "be verbose", &mut verbose);
+  let args = vec!["a", "-verbose", "b"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+}
+
+#[test]
+fn dyn_flag() {
+  let mut plugins = [
+    (
+      "retries".to_string(),
+      DynFlag::new(|s| {
+        s.parse::<u8>()
+          .map(|v| Box::new(v) as _)
+          .map_err(|e| e.to_string())
+      }),
+    ),
+    (
+      "name".to_string(),
+      DynFlag::new(|s| Ok(Box::new(s.to_uppercase()))),
+    ),
+  ];
+  let mut fs = FlagSet::new();
+  for (name, flag) in plugins.iter_mut() {
+    fs.add_owned(name.clone(), format!("plugin flag {}", name), flag);
+  }
+  let args = vec!["-retries", "3", "-name", "cache"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["-retries", "many"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(plugins[0].1.get::<u8>(), Some(&3));
+  assert_eq!(plugins[0].1.get::<String>(), None);
+  assert_eq!(plugins[1].1.get::<String>(), Some(&"CACHE".to_string()));
+}
+
+#[test]
+fn sorted_unique() {
+  let mut ids: SortedUnique<u32> = SortedUnique::default();
+  let mut fs = FlagSet::new();
+  fs.add("ids", "ids to process", &mut ids);
+  assert!(fs
+    .parse(vec!["-ids", "1,4,9"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["-ids", "1,4,4"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "ids".to_string(),
+      "duplicate element \"4\"".to_string()
+    ))
+  );
+  assert_eq!(
+    fs.parse(vec!["-ids", "1,9,4"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "ids".to_string(),
+      "\"4\" is out of order after \"9\"".to_string()
+    ))
+  );
+  assert_eq!(ids.0, vec![1, 4, 9]);
+}
+
+#[test]
+fn on_complete() {
+  let calls = RefCell::new(vec![]);
+  let mut record_out = |v: &str| calls.borrow_mut().push(format!("out={}", v));
+  let mut record_level = |v: &str| calls.borrow_mut().push(format!("level={}", v));
+  let mut level: Option<u8> = None;
+  let mut out: Option<String> = None;
+  let mut quiet = false;
+  let mut fs = FlagSet::new();
+  fs.add("level", "level to use", &mut level);
+  fs.add("out", "output file", &mut out);
+  fs.add("quiet", "be quiet", &mut quiet);
+  fs.add_on_complete("out", &mut record_out);
+  fs.add_on_complete("level", &mut record_level);
+  assert!(fs
+    .parse(
+      vec!["-out", "a.txt", "-level", "x"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_err());
+  for _ in 0..2 {
+    assert!(fs
+      .parse(vec!["-quiet"].into_iter().map(String::from))
+      .is_ok());
+  }
+  assert!(calls.borrow().is_empty());
+  assert!(fs
+    .parse(
+      vec!["-out", "b.txt", "-level", "3"]
+        .into_iter()
+        .map(String::from)
+    )
+    .is_ok());
+  assert_eq!(
+    *calls.borrow(),
+    vec!["level=3".to_string(), "out=b.txt".to_string()]
+  );
+}
+
+#[test]
+fn sort_spec() {
+  let mut sort: Option<SortSpec> = None;
+  let mut sort_by: Option<SortSpec> = None;
+  let mut fs = FlagSet::new();
+  fs.add("sort", "sort direction", &mut sort);
+  fs.add("sort-by", "fields to sort by", &mut sort_by);
+  let args = vec!["-sort", "desc", "-sort-by", "name:asc,age:desc"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert!(fs
+    .parse(vec!["-sort-by", "name:up"].into_iter().map(String::from))
+    .is_err());
+  assert!(fs
+    .parse(vec!["-sort", "sideways"].into_iter().map(String::from))
+    .is_err());
+  assert_eq!(sort, Some(SortSpec::Simple(Direction::Desc)));
+  assert_eq!(
+    sort_by,
+    Some(SortSpec::Keys(vec![
+      ("name".to_string(), Direction::Asc),
+      ("age".to_string(), Direction::Desc),
+    ]))
+  );
+  assert_eq!(
+    Direction::Desc.apply(1.cmp(&2)),
+    std::cmp::Ordering::Greater
+  );
+}
+
+#[test]
+fn matches_schema() {
+  let mut verbose = false;
+  let mut out: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.add("out", "", &mut out);
+  let mut schema = vec![
+    FlagSpec {
+      name: "verbose",
+      expects_value: false,
+      has_help: true,
+    },
+    FlagSpec {
+      name: "out",
+      expects_value: true,
+      has_help: false,
+    },
+  ];
+  assert_eq!(fs.matches_schema(&schema), Ok(()));
+  schema[1].has_help = true;
+  schema.push(FlagSpec {
+    name: "level",
+    expects_value: true,
+    has_help: true,
+  });
+  assert_eq!(
+    fs.matches_schema(&schema[1..]),
+    Err(vec![
+      "flag -out should have help".to_string(),
+      "missing flag -level".to_string(),
+      "unexpected flag -verbose".to_string(),
+    ])
+  );
+}
+
+#[test]
+fn clear_suffix() {
+  let mut inc: Vec<String> = vec!["default".to_string()];
+  let mut out: Option<String> = None;
+  let mut lib: Trimmed<Vec<String>> = Trimmed(vec![]);
+  let mut path: Interpolated<Vec<String>> = Interpolated::new(vec![], Undefined::Keep);
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('I'), "inc", "include dirs", &mut inc);
+  fs.add("out", "output file", &mut out);
+  fs.add("lib", "libraries", &mut lib);
+  fs.add("path", "search paths", &mut path);
+  let args = vec!["--inc", "a", "-I", "b", "--inc-", "--inc", "c"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert_eq!(
+    fs.parse(vec!["-out-"].into_iter().map(String::from)),
+    Err(ParseError::UnknownFlag("out-".to_string()))
+  );
+  let args = vec!["-lib", " m ", "-lib-", "-path", "a", "-path-", "-path", "b"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert_eq!(inc, vec!["c".to_string()]);
+  assert!(lib.0.is_empty());
+  assert_eq!(path.value, vec!["b".to_string()]);
+}
+
+#[test]
+fn cleared_flag_unset() {
+  let calls = RefCell::new(vec![]);
+  let mut record = |v: &str| calls.borrow_mut().push(v.to_string());
+  let mut inc: Vec<String> = vec![];
+  let mut fs = FlagSet::new();
+  fs.add("inc", "include dirs", &mut inc);
+  fs.set_required("inc");
+  fs.add_on_complete("inc", &mut record);
+  assert_eq!(
+    fs.parse(vec!["-inc", "a", "-inc-"].into_iter().map(String::from)),
+    Err(ParseError::MissingRequired(vec!["inc".to_string()]))
+  );
+  assert_eq!(fs.value_of::<String>("inc"), None);
+  assert!(calls.borrow().is_empty());
+}
+
+#[test]
+fn log_filter() {
+  let mut log: Option<LogFilter> = None;
+  let mut fs = FlagSet::new();
+  fs.add("log", "log directives", &mut log);
+  let args = vec!["-log", "warn,mymod=debug,mymod::db=TRACE"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert_eq!(
+    fs.parse(vec!["-log", "mymod=loud"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "log".to_string(),
+      "\"mymod=loud\": unknown log level \"loud\"".to_string()
+    ))
+  );
+  assert!(fs
+    .parse(vec!["-log", "=debug"].into_iter().map(String::from))
+    .is_err());
+  let log = log.unwrap();
+  assert_eq!(log.targets.len(), 2);
+  assert_eq!(log.level_for("mymod::db::pool"), Some(LogLevel::Trace));
+  assert_eq!(log.level_for("mymod::net"), Some(LogLevel::Debug));
+  assert_eq!(log.level_for("mymodule"), Some(LogLevel::Warn));
+}
+
+#[test]
+fn manpage_options() {
+  let mut verbose = false;
+  let mut out: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('v'), "verbose", "be verbose", &mut verbose);
+  fs.add("out-file", "file to write", &mut out);
+  fs.set_metavar("out-file", "PATH");
+  assert_eq!(
+    fs.manpage_options(),
+    ".SH OPTIONS\n.TP\n\\fB\\-v\\fR, \\fB\\-\\-verbose\\fR\nbe verbose\n\
+     .TP\n\\fB\\-out\\-file\\fR \\fIPATH\\fR\nfile to write\n"
+  );
+}
+
+#[test]
+fn apply_pairs() {
+  let mut level: Option<u8> = None;
+  let mut tags: Vec<String> = vec![];
+  let (mut verbose, mut quiet) = (false, false);
+  let mut fs = FlagSet::new();
+  fs.add_named(Some('l'), "level", "level to use", &mut level);
+  fs.add("tag", "tags to add", &mut tags);
+  fs.add("verbose", "be verbose", &mut verbose);
+  fs.add("quiet", "be quiet", &mut quiet);
+  let pairs = |p: &[(&str, &str)]| -> Vec<(String, String)> {
+    p.iter()
+      .map(|&(k, v)| (k.to_string(), v.to_string()))
+      .collect()
+  };
+  assert_eq!(
+    fs.apply_pairs(&pairs(&[("l", "3"), ("tag", "a"), ("tag", "-b")])),
+    Ok(())
+  );
+  assert_eq!(
+    fs.apply_pairs(&pairs(&[("colour", "red")])),
+    Err(ParseError::UnknownFlag("colour".to_string()))
+  );
+  assert_eq!(
+    fs.apply_pairs(&pairs(&[("level", "high")])),
+    Err(ParseError::ParseFromFailure(
+      "level".to_string(),
+      "\"high\": invalid digit found in string".to_string()
+    ))
+  );
+  assert_eq!(
+    fs.apply_pairs(&pairs(&[("verbose", "false"), ("quiet", "true")])),
+    Ok(())
+  );
+  assert_eq!(
+    fs.apply_pairs(&pairs(&[("verbose", "yes please")])),
+    Err(ParseError::ParseFromFailure(
+      "verbose".to_string(),
+      "invalid bool \"yes please\"".to_string()
+    ))
+  );
+  assert_eq!(fs.value_of::<u8>("level"), Some(3));
+  assert_eq!(level, Some(3));
+  assert!(!verbose);
+  assert!(quiet);
+  assert_eq!(tags, vec!["a".to_string(), "-b".to_string()]);
+}
+
+#[test]
+fn require_any() {
+  let mut input = Preset(String::from("in.txt"));
+  let mut stdin = false;
+  let mut fs = FlagSet::new();
+  fs.add_default("input", "file to read", &mut input);
+  fs.add("stdin", "read from stdin", &mut stdin);
+  fs.require_any(&["input", "stdin"]);
+  assert_eq!(
+    fs.parse(std::iter::empty()),
+    Err(ParseError::NoneProvided(vec![
+      "input".to_string(),
+      "stdin".to_string()
+    ]))
+  );
+  assert!(fs
+    .parse(vec!["-stdin"].into_iter().map(String::from))
+    .is_ok());
+  assert!(stdin);
+}
+
+#[test]
+fn collect_warnings() {
+  let mut out: Option<String> = None;
+  let mut output: Option<String> = None;
+  let mut fs = FlagSet::new();
+  fs.add("out", "output file", &mut out);
+  fs.add("output", "output file", &mut output);
+  fs.set_deprecated("out", "use -output instead");
+  let args = vec!["-out", "a.txt", "-output", "b.txt"];
+  assert_eq!(
+    fs.parse_collect_warnings(args.into_iter().map(String::from)),
+    Ok((
+      vec![],
+      vec!["flag -out is deprecated: use -output instead".to_string()]
+    ))
+  );
+  let mut written = vec![];
+  let mut fs = FlagSet::new().with_writer(&mut written);
+  fs.add("out", "output file", &mut out);
+  fs.set_deprecated("out", "use -output instead");
+  assert!(fs
+    .parse(vec!["--out", "c.txt"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    String::from_utf8(written).unwrap(),
+    "warning: flag -out is deprecated: use -output instead\n"
+  );
+}
+
+#[test]
+fn derived_default() {
+  let mut input: Option<String> = None;
+  let mut output: Option<String> = None;
+  let derive = |seen: &HashMap<String, String>| seen.get("input").map(|i| format!("{}.out", i));
+  let mut fs = FlagSet::new();
+  fs.add("input", "file to read", &mut input);
+  fs.add("output", "file to write", &mut output);
+  fs.add_derived_default("output", &derive);
+  for input in ["a.csv", "b.csv"] {
+    assert!(fs
+      .parse(vec!["-input", input].into_iter().map(String::from))
+      .is_ok());
+    assert_eq!(
+      fs.value_of::<String>("output"),
+      Some(format!("{}.out", input))
+    );
+  }
+  let args = vec!["-input", "c.csv", "-output", "result.txt"];
+  assert!(fs.parse(args.into_iter().map(String::from)).is_ok());
+  assert_eq!(output, Some("result.txt".to_string()));
+  let mut outputs: Vec<String> = vec![];
+  let mut fs = FlagSet::new();
+  fs.add("input", "file to read", &mut input);
+  fs.add("output", "file to write", &mut outputs);
+  fs.add_derived_default("output", &derive);
+  for input in ["a", "b"] {
+    assert!(fs
+      .parse(vec!["-input", input].into_iter().map(String::from))
+      .is_ok());
+  }
+  assert_eq!(outputs, vec!["b.out".to_string()]);
+  let mut output: Option<String> = None;
+  let mut stdout = false;
+  let mut fs = FlagSet::new();
+  fs.add("input", "file to read", &mut input);
+  fs.add("output", "file to write", &mut output);
+  fs.add("stdout", "write to stdout", &mut stdout);
+  fs.add_derived_default("output", &derive);
+  fs.require_any(&["output", "stdout"]);
+  assert_eq!(
+    fs.parse(vec!["-input", "a.csv"].into_iter().map(String::from)),
+    Err(ParseError::NoneProvided(vec![
+      "output".to_string(),
+      "stdout".to_string()
+    ]))
+  );
+}
+
+#[test]
+fn any_of() {
+  let mut count: Option<u32> = None;
+  let allowed = ["all".to_string(), "none".to_string()];
+  let mut named = OneOf::new(&allowed);
+  let mut jobs = AnyOf::new(vec![&mut count, &mut named]);
+  let mut fs = FlagSet::new();
+  fs.add("jobs", "number of jobs, or all", &mut jobs);
+  assert!(fs
+    .parse(vec!["-jobs", "all"].into_iter().map(String::from))
+    .is_ok());
+  assert_eq!(
+    fs.parse(vec!["-jobs", "many"].into_iter().map(String::from)),
+    Err(ParseError::ParseFromFailure(
+      "jobs".to_string(),
+      "no format accepted \"many\": \"many\": invalid digit found in string; \"many\" is not one of [\"all\", \"none\"]".to_string()
+    ))
+  );
+  assert_eq!(jobs.matched, Some(1));
+  assert_eq!(count, None);
+  assert_eq!(named.value, Some("all".to_string()));
+}
+
+#[test]
+fn value_transform() {
+  let lower = |v: &str| v.to_lowercase();
+  let mut mode: Option<String> = None;
+  let mut tags: Vec<String> = vec![];
+  let mut fs = FlagSet::new().with_value_transform(&lower);
+  fs.add_dynamic_choice(
+    "mode",
+    "mode to run in",
+    vec!["fast".to_string()],
+    &mut mode,
+  );
+  fs.add("tag", "tags to add", &mut tags);
+  let args = vec!["-mode", "FAST", "-tag", "Red", "-tag", "BLUE", "File"];
+  assert_eq!(
+    fs.parse(args.into_iter().map(String::from)),
+    Ok(vec!["File".to_string()])
+  );
+  assert_eq!(mode, Some("fast".to_string()));
+  assert_eq!(tags, vec!["red".to_string(), "blue".to_string()]);
+}
@@ -0,0 +1,944 @@
+use crate::flag::Flaggable;
+use std::{
+  any::Any,
+  cell::Cell,
+  cmp::Ordering,
+  collections::HashMap,
+  convert::TryFrom,
+  env, fmt, fs,
+  path::{Path, PathBuf},
+  rc::Rc,
+  str::FromStr,
+  time::Duration,
+};
+
+/// Where a program should read input from or write output to.
+/// `-`, `0` and `/dev/stdin` refer to stdin, `1` and `/dev/stdout` to stdout,
+/// and anything else is a path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputSource {
+  Stdin,
+  Stdout,
+  Path(PathBuf),
+}
+
+impl FromStr for InputSource {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "" => Err(String::from("empty path")),
+      "-" | "0" | "/dev/stdin" => Ok(InputSource::Stdin),
+      "1" | "/dev/stdout" => Ok(InputSource::Stdout),
+      p => Ok(InputSource::Path(PathBuf::from(p))),
+    }
+  }
+}
+
+/// Accumulates a bitmask from repeated occurrences of a flag,
+/// where each value name maps to a bit index.
+#[derive(Clone, Debug)]
+pub struct BitAccum {
+  pub bits: u64,
+  table: &'static [(&'static str, u32)],
+}
+
+impl BitAccum {
+  /// Creates an empty mask with a table from value names to bit indices.
+  pub fn new(table: &'static [(&'static str, u32)]) -> Self { BitAccum { bits: 0, table } }
+}
+
+impl Flaggable for BitAccum {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let bit = self
+      .table
+      .iter()
+      .find(|(name, _)| *name == s)
+      .and_then(|&(_, bit)| 1u64.checked_shl(bit))
+      .ok_or_else(|| s.to_string())?;
+    self.bits |= bit;
+    Ok(())
+  }
+  fn clear(&mut self) -> bool {
+    self.bits = 0;
+    true
+  }
+}
+
+/// A comma separated list of `host:port` pairs, such as `host1:9092,[::1]:9092`.
+/// Hosts are not resolved, so they may be names or IP addresses.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostPortList(pub Vec<(String, u16)>);
+
+fn host_port(s: &str) -> Result<(String, u16), String> {
+  let (host, port) = match s.rsplit_once(':') {
+    None => return Err(format!("missing port in \"{}\"", s)),
+    Some(v) => v,
+  };
+  let host = match host.strip_prefix('[') {
+    Some(h) => h.strip_suffix(']'),
+    None if host.contains(':') => None,
+    None => Some(host),
+  };
+  match (host, port.parse()) {
+    (Some(h), Ok(p)) if !h.is_empty() => Ok((h.to_string(), p)),
+    _ => Err(format!("invalid host and port \"{}\"", s)),
+  }
+}
+
+impl Flaggable for HostPortList {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    self.0 = s.split(',').map(host_port).collect::<Result<_, _>>()?;
+    Ok(())
+  }
+}
+
+/// A number of bytes, with an optional binary suffix such as `512K`, `100M` or `2GiB`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, suffix) = s.split_at(split);
+    let shift = match suffix.to_ascii_lowercase().as_str() {
+      "" | "b" => 0,
+      "k" | "kb" | "kib" => 10,
+      "m" | "mb" | "mib" => 20,
+      "g" | "gb" | "gib" => 30,
+      "t" | "tb" | "tib" => 40,
+      _ => return Err(format!("unknown size suffix \"{}\"", suffix)),
+    };
+    let num: u64 = num.parse().map_err(|_| format!("invalid size \"{}\"", s))?;
+    num
+      .checked_mul(1 << shift)
+      .map(ByteSize)
+      .ok_or_else(|| format!("size \"{}\" is too large", s))
+  }
+}
+
+/// A signed change in bytes, such as `+100M` or `-50M`.
+/// Values without a sign are positive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SignedByteSize(pub i64);
+
+impl FromStr for SignedByteSize {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (neg, rest) = match s.strip_prefix('-') {
+      Some(rest) => (true, rest),
+      None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let ByteSize(n) = rest.parse()?;
+    let n = i64::try_from(n).map_err(|_| format!("size \"{}\" is too large", s))?;
+    Ok(SignedByteSize(if neg { -n } else { n }))
+  }
+}
+
+/// A float, possibly in scientific notation such as `1.5e3`,
+/// which rejects values with more significant digits than a given precision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PreciseFloat {
+  pub value: Option<f64>,
+  digits: usize,
+}
+
+impl PreciseFloat {
+  /// Creates an empty float accepting at most `digits` significant digits.
+  pub fn new(digits: usize) -> Self {
+    PreciseFloat {
+      value: None,
+      digits,
+    }
+  }
+}
+
+impl Flaggable for PreciseFloat {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let v: f64 = s.parse().map_err(|_| format!("invalid number \"{}\"", s))?;
+    if !v.is_finite() {
+      return Err(format!("invalid number \"{}\"", s));
+    }
+    let mantissa = s.split(['e', 'E']).next().unwrap_or("");
+    let digits = mantissa
+      .chars()
+      .filter(char::is_ascii_digit)
+      .skip_while(|&c| c == '0')
+      .count();
+    if digits > self.digits {
+      return Err(format!(
+        "\"{}\" has more than {} significant digits",
+        s, self.digits
+      ));
+    }
+    self.value = Some(v);
+    Ok(())
+  }
+}
+
+/// A time of day written as `HH:MM` or `HH:MM:SS`, in 24 hour time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl FromStr for TimeOfDay {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid time \"{}\"", s);
+    let mut parts = s.split(':').map(|p| match p.len() {
+      2 => p.parse::<u8>().map_err(|_| invalid()),
+      _ => Err(invalid()),
+    });
+    let hour = parts.next().unwrap_or_else(|| Err(invalid()))?;
+    let minute = parts.next().unwrap_or_else(|| Err(invalid()))?;
+    let second = parts.next().unwrap_or(Ok(0))?;
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+      return Err(invalid());
+    }
+    Ok(TimeOfDay {
+      hour,
+      minute,
+      second,
+    })
+  }
+}
+
+/// A time of day with an offset from UTC, such as `14:30+02:00` or `09:00Z`.
+/// Times without an offset are taken to be in UTC.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OffsetTime {
+  pub time: TimeOfDay,
+  /// Offset from UTC in minutes, between -14:00 and +14:00.
+  pub offset_minutes: i16,
+}
+
+impl FromStr for OffsetTime {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (time, offset) = match s.find(['+', '-']) {
+      Some(i) => s.split_at(i),
+      None => (s.strip_suffix('Z').unwrap_or(s), ""),
+    };
+    let time = time.parse()?;
+    if offset.is_empty() {
+      return Ok(OffsetTime {
+        time,
+        offset_minutes: 0,
+      });
+    }
+    let invalid = || format!("invalid UTC offset \"{}\"", offset);
+    let (sign, hm) = offset.split_at(1);
+    let (h, m) = hm.split_once(':').unwrap_or((hm, "00"));
+    let (h, m): (i16, i16) = match (h.len(), m.len(), h.parse(), m.parse()) {
+      (2, 2, Ok(h), Ok(m)) if m < 60 => (h, m),
+      _ => return Err(invalid()),
+    };
+    let minutes = h * 60 + m;
+    if minutes > 14 * 60 {
+      return Err(invalid());
+    }
+    Ok(OffsetTime {
+      time,
+      offset_minutes: if sign == "-" { -minutes } else { minutes },
+    })
+  }
+}
+
+/// A comma separated list of bools, such as `on,off,on`.
+/// Accepts `on`, `off`, `true`, `false`, `1` and `0`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BoolList(pub Vec<bool>);
+
+impl Flaggable for BoolList {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let bool_at = |(i, b): (usize, &str)| match b {
+      "on" | "true" | "1" => Ok(true),
+      "off" | "false" | "0" => Ok(false),
+      _ => Err(format!("invalid bool \"{}\" at position {}", b, i)),
+    };
+    self.0 = s
+      .split(',')
+      .enumerate()
+      .map(bool_at)
+      .collect::<Result<_, _>>()?;
+    Ok(())
+  }
+}
+
+/// An email address, validated only to have a single `@` between a non-empty local part and domain.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Email(pub String);
+
+impl Email {
+  /// The part of the address before the `@`.
+  pub fn local(&self) -> &str { self.0.split('@').next().unwrap_or("") }
+  /// The part of the address after the `@`.
+  pub fn domain(&self) -> &str { self.0.rsplit('@').next().unwrap_or("") }
+}
+
+impl FromStr for Email {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.split_once('@') {
+      Some((local, domain)) if !local.is_empty() && !domain.is_empty() && !domain.contains('@') => {
+        Ok(Email(s.to_string()))
+      },
+      _ => Err(format!("invalid email address \"{}\"", s)),
+    }
+  }
+}
+
+/// A path where a leading `~` is expanded to the home directory,
+/// taken from `HOME` or else `USERPROFILE`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExpandedPath(pub PathBuf);
+
+impl FromStr for ExpandedPath {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let rest = match s.strip_prefix('~') {
+      Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => rest,
+      _ => return Ok(ExpandedPath(PathBuf::from(s))),
+    };
+    let home = env::var("HOME")
+      .or_else(|_| env::var("USERPROFILE"))
+      .map_err(|_| format!("cannot expand \"{}\", no home directory", s))?;
+    let mut path = PathBuf::from(home);
+    path.push(rest.trim_start_matches(['/', '\\']));
+    Ok(ExpandedPath(path))
+  }
+}
+
+/// A value which must be one of an allowed set, such as `--retries` accepting only 0, 1, 3 or 5.
+#[derive(Clone, Debug)]
+pub struct OneOf<'s, T> {
+  pub value: Option<T>,
+  allowed: &'s [T],
+}
+
+impl<'s, T> OneOf<'s, T> {
+  /// Creates an empty value which may only be set to one of `allowed`.
+  pub fn new(allowed: &'s [T]) -> Self {
+    OneOf {
+      value: None,
+      allowed,
+    }
+  }
+}
+
+impl<T: FromStr + PartialEq + fmt::Debug> Flaggable for OneOf<'_, T> {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    match T::from_str(s) {
+      Ok(v) if self.allowed.contains(&v) => {
+        self.value = Some(v);
+        Ok(())
+      },
+      _ => Err(format!("\"{}\" is not one of {:?}", s, self.allowed)),
+    }
+  }
+}
+
+/// Trims surrounding whitespace from values before parsing them into the inner flag,
+/// such as values coming from quoted shell expansions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trimmed<F>(pub F);
+
+impl<F: Flaggable> Flaggable for Trimmed<F> {
+  fn expects_value(&self) -> bool { self.0.expects_value() }
+  fn parse_from(&mut self, s: &str) -> Result<(), String> { self.0.parse_from(s.trim()) }
+  fn clear(&mut self) -> bool { self.0.clear() }
+}
+
+/// What `Interpolated` does with a variable which is not set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Undefined {
+  /// Fails to parse the value.
+  Error,
+  /// Leaves the reference as written, such as `${VAR}`.
+  Keep,
+}
+
+/// Substitutes environment variables written as `${VAR}` into values
+/// before parsing them into the inner flag, such as `${HOME}/cfg`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interpolated<F> {
+  pub value: F,
+  pub undefined: Undefined,
+}
+
+impl<F> Interpolated<F> {
+  pub fn new(value: F, undefined: Undefined) -> Self { Self { value, undefined } }
+}
+
+impl<F: Flaggable> Flaggable for Interpolated<F> {
+  fn expects_value(&self) -> bool { self.value.expects_value() }
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+      let end = match rest[start..].find('}') {
+        Some(end) => start + end,
+        None => break,
+      };
+      out.push_str(&rest[..start]);
+      let var = &rest[start + 2..end];
+      match (env::var(var), self.undefined) {
+        (Ok(val), _) => out.push_str(&val),
+        (Err(_), Undefined::Keep) => out.push_str(&rest[start..=end]),
+        (Err(_), Undefined::Error) => return Err(format!("${{{}}} is not set", var)),
+      }
+      rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    self.value.parse_from(&out)
+  }
+  fn clear(&mut self) -> bool { self.value.clear() }
+}
+
+/// Parses UUIDs such as `550e8400-e29b-41d4-a716-446655440000`, surfacing why invalid ones failed.
+#[cfg(feature = "uuid")]
+impl Flaggable for uuid::Uuid {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    *self = uuid::Uuid::parse_str(s).map_err(|e| e.to_string())?;
+    Ok(())
+  }
+}
+
+/// Glob patterns such as `*.rs`, compiled as they are parsed to catch malformed ones.
+/// Each time the flag is passed another pattern is collected.
+#[cfg(feature = "glob")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlobList(pub Vec<glob::Pattern>);
+
+#[cfg(feature = "glob")]
+impl GlobList {
+  /// Whether any of the patterns match a path.
+  pub fn matches(&self, path: &str) -> bool { self.0.iter().any(|p| p.matches(path)) }
+}
+
+#[cfg(feature = "glob")]
+impl Flaggable for GlobList {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let pattern = glob::Pattern::new(s).map_err(|e| format!("invalid glob \"{}\": {}", s, e))?;
+    self.0.push(pattern);
+    Ok(())
+  }
+  fn clear(&mut self) -> bool {
+    self.0.clear();
+    true
+  }
+}
+
+/// Parses a duration with a unit, such as `100ms`, `2s`, `1.5m` or `1h`.
+/// Accepted units are `ns`, `us`, `ms`, `s`, `m` and `h`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+  let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+  let (num, unit) = s.split_at(split);
+  let scale = match unit {
+    "ns" => 1e-9,
+    "us" => 1e-6,
+    "ms" => 1e-3,
+    "s" => 1.,
+    "m" => 60.,
+    "h" => 3600.,
+    "" => return Err(format!("missing unit in duration \"{}\"", s)),
+    _ => return Err(format!("unknown unit in duration \"{}\"", s)),
+  };
+  let num: f64 = num
+    .parse()
+    .map_err(|_| format!("invalid duration \"{}\"", s))?;
+  Duration::try_from_secs_f64(num * scale).map_err(|_| format!("invalid duration \"{}\"", s))
+}
+
+/// A duration which must be greater than zero, such as for a timeout
+/// where `0s` would otherwise silently disable it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PositiveDuration(pub Duration);
+
+impl FromStr for PositiveDuration {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let positive = || format!("duration must be positive, got \"{}\"", s);
+    if s.starts_with('-') {
+      return Err(positive());
+    }
+    match parse_duration(s)? {
+      d if d.is_zero() => Err(positive()),
+      d => Ok(PositiveDuration(d)),
+    }
+  }
+}
+
+/// A range of durations written as `min..max`, such as `100ms..2s`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DurationRange {
+  pub min: Duration,
+  pub max: Duration,
+}
+
+impl FromStr for DurationRange {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (min, max) = s
+      .split_once("..")
+      .ok_or_else(|| format!("invalid range \"{}\", expected min..max", s))?;
+    let (min, max) = (parse_duration(min)?, parse_duration(max)?);
+    if min > max {
+      return Err(format!("range \"{}\" has its minimum above its maximum", s));
+    }
+    Ok(DurationRange { min, max })
+  }
+}
+
+/// A comma separated list of exactly `N` values, such as `1,2,3,4` for `Array<f64, 4>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Array<T, const N: usize>(pub [T; N]);
+
+impl<T: FromStr, const N: usize> FromStr for Array<T, N> {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let items = s
+      .split(',')
+      .map(|v| T::from_str(v).map_err(|_| format!("invalid element \"{}\"", v)))
+      .collect::<Result<Vec<T>, _>>()?;
+    let len = items.len();
+    <[T; N]>::try_from(items)
+      .map(Array)
+      .map_err(|_| format!("expected {} elements, found {}", N, len))
+  }
+}
+
+/// A temperature such as `22C`, `72F` or `295K`, stored in Kelvin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd)]
+pub struct Temperature(pub f64);
+
+impl Temperature {
+  pub fn kelvin(self) -> f64 { self.0 }
+  pub fn celsius(self) -> f64 { self.0 - 273.15 }
+  pub fn fahrenheit(self) -> f64 { self.celsius() * 9. / 5. + 32. }
+}
+
+impl FromStr for Temperature {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid temperature \"{}\", expected a C, F or K suffix", s);
+    let unit = s.chars().last().ok_or_else(invalid)?;
+    let num = s[..s.len() - unit.len_utf8()].trim_end_matches('°');
+    let num: f64 = num.parse().map_err(|_| invalid())?;
+    let kelvin = match unit.to_ascii_uppercase() {
+      'C' => num + 273.15,
+      'F' => (num - 32.) * 5. / 9. + 273.15,
+      'K' => num,
+      _ => return Err(invalid()),
+    };
+    if !kelvin.is_finite() {
+      return Err(invalid());
+    }
+    if kelvin < 0. {
+      return Err(format!("temperature \"{}\" is below absolute zero", s));
+    }
+    Ok(Temperature(kelvin))
+  }
+}
+
+/// An integer parsed in a base set by another flag, such as `--base 16 --value ff`.
+/// The base is shared with the flag setting it, so it must be passed first.
+#[derive(Clone, Debug, Default)]
+pub struct BaseAware {
+  pub value: Option<i64>,
+  base: Rc<Cell<u32>>,
+}
+
+impl BaseAware {
+  /// Creates an empty value parsed in whichever base `base` holds at the time.
+  pub fn new(base: Rc<Cell<u32>>) -> Self { BaseAware { value: None, base } }
+}
+
+impl Flaggable for BaseAware {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let base = self.base.get();
+    if !(2..=36).contains(&base) {
+      return Err(format!("invalid base {}", base));
+    }
+    let v = i64::from_str_radix(s, base)
+      .map_err(|_| format!("invalid base {} number \"{}\"", base, s))?;
+    self.value = Some(v);
+    Ok(())
+  }
+}
+
+/// A comma separated list of `key:value` pairs, such as `a:1,b:2` for `PairList<String, i32>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PairList<K, V>(pub Vec<(K, V)>);
+
+impl<K, V> Default for PairList<K, V> {
+  fn default() -> Self { PairList(vec![]) }
+}
+
+impl<K: FromStr, V: FromStr> Flaggable for PairList<K, V> {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let pair = |p: &str| match p.split_once(':') {
+      Some((k, v)) => match (K::from_str(k), V::from_str(v)) {
+        (Ok(k), Ok(v)) => Ok((k, v)),
+        _ => Err(format!("invalid pair \"{}\"", p)),
+      },
+      None => Err(format!("missing ':' in pair \"{}\"", p)),
+    };
+    self.0 = s.split(',').map(pair).collect::<Result<_, _>>()?;
+    Ok(())
+  }
+}
+
+/// A value which is either one of a known set, or some other value passed through as is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Open<T> {
+  Known(T),
+  Other(String),
+}
+
+/// Parses known names into their values, and anything else into `Open::Other`,
+/// for enums which should be forward compatible with values added later.
+#[derive(Clone, Debug)]
+pub struct OpenEnum<'s, T> {
+  pub value: Option<Open<T>>,
+  known: &'s [(&'s str, T)],
+}
+
+impl<'s, T> OpenEnum<'s, T> {
+  /// Creates an empty value with a table from known names to values.
+  pub fn new(known: &'s [(&'s str, T)]) -> Self { OpenEnum { value: None, known } }
+}
+
+impl<T: Clone> Flaggable for OpenEnum<'_, T> {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let v = match self.known.iter().find(|(name, _)| *name == s) {
+      Some((_, v)) => Open::Known(v.clone()),
+      None => Open::Other(s.to_string()),
+    };
+    self.value = Some(v);
+    Ok(())
+  }
+}
+
+/// A path which is checked to be writable by opening it, or if it does not exist,
+/// by creating and removing it. This fails fast for output paths, rather than after doing
+/// expensive work.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WritablePath(pub PathBuf);
+
+impl FromStr for WritablePath {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let path = Path::new(s);
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true);
+    let writable = if path.exists() {
+      opts.open(path).is_ok()
+    } else {
+      opts.create_new(true).open(path).is_ok() && fs::remove_file(path).is_ok()
+    };
+    if !writable {
+      return Err(format!("cannot write to {}", s));
+    }
+    Ok(WritablePath(path.to_path_buf()))
+  }
+}
+
+/// Entries built from indexed keys such as `--item.0.name foo --item.1.name bar`,
+/// added with `FlagSet::add_indexed`. Each value is parsed from `index.key=value`,
+/// and entries are created up to the largest index seen, which may be at most `MAX_INDEX`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexedMap(pub Vec<HashMap<String, String>>);
+
+impl IndexedMap {
+  /// Largest index accepted, so that input cannot allocate an unbounded number of entries.
+  pub const MAX_INDEX: usize = 1023;
+}
+
+impl Flaggable for IndexedMap {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let invalid = || format!("expected index.key=value, got \"{}\"", s);
+    let (index, rest) = s.split_once('.').ok_or_else(invalid)?;
+    let (key, value) = rest.split_once('=').ok_or_else(invalid)?;
+    let index: usize = index.parse().map_err(|_| invalid())?;
+    let len = index
+      .checked_add(1)
+      .filter(|_| index <= Self::MAX_INDEX)
+      .ok_or_else(|| format!("index {} is too large", index))?;
+    if self.0.len() < len {
+      self.0.resize_with(len, HashMap::new);
+    }
+    self.0[index].insert(key.to_string(), value.to_string());
+    Ok(())
+  }
+}
+
+/// An amount of money in minor units, such as cents, with a currency code.
+/// Parsed from a leading symbol such as `$12.99` or a trailing code such as `12.99USD`,
+/// with at most as many decimal places as the currency has minor units, such as none for `¥500`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Money {
+  pub minor: u64,
+  pub currency: String,
+}
+
+impl FromStr for Money {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let symbols = [('$', "USD"), ('€', "EUR"), ('£', "GBP"), ('¥', "JPY")];
+    let symbol = symbols
+      .iter()
+      .find_map(|&(c, code)| Some((s.strip_prefix(c)?, code)));
+    let (amount, currency) = match symbol {
+      Some(v) => v,
+      None if s.len() > 3 && s.is_char_boundary(s.len() - 3) => s.split_at(s.len() - 3),
+      None => return Err(format!("missing currency in \"{}\"", s)),
+    };
+    if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+      return Err(format!("invalid currency \"{}\"", currency));
+    }
+    let exponent = match currency {
+      "JPY" | "KRW" | "VND" | "CLP" | "ISK" => 0,
+      "BHD" | "KWD" | "OMR" | "JOD" | "TND" => 3,
+      _ => 2,
+    };
+    let (units, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let digits = |d: &str| d.bytes().all(|b| b.is_ascii_digit());
+    if units.is_empty() || !digits(units) || !digits(fraction) || fraction.len() > exponent {
+      return Err(format!("invalid amount \"{}\"", amount));
+    }
+    // The units followed by the fraction padded to the currency's exponent.
+    let minor = format!("{}{:0<2$}", units, fraction, exponent)
+      .parse::<u64>()
+      .map_err(|_| format!("amount \"{}\" is too large", amount))?;
+    Ok(Money {
+      minor,
+      currency: currency.to_string(),
+    })
+  }
+}
+
+type DynParser = Box<dyn Fn(&str) -> Result<Box<dyn Any>, String>>;
+
+/// A flag whose type is only known at runtime, such as one registered by a plugin.
+/// Values are parsed by a closure into a type-erased box, and read back with `get`.
+pub struct DynFlag {
+  parse: DynParser,
+  value: Option<Box<dyn Any>>,
+}
+
+impl DynFlag {
+  pub fn new<F>(parse: F) -> Self
+  where
+    F: Fn(&str) -> Result<Box<dyn Any>, String> + 'static, {
+    DynFlag {
+      parse: Box::new(parse),
+      value: None,
+    }
+  }
+  /// The last parsed value, if one was parsed and it is a `T`.
+  pub fn get<T: 'static>(&self) -> Option<&T> { self.value.as_ref()?.downcast_ref() }
+}
+
+impl fmt::Debug for DynFlag {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("DynFlag")
+      .field("parsed", &self.value.is_some())
+      .finish()
+  }
+}
+
+impl Flaggable for DynFlag {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    self.value = Some((self.parse)(s)?);
+    Ok(())
+  }
+}
+
+/// A comma separated list whose elements must be strictly increasing, such as `1,4,9`,
+/// rejecting duplicates and out of order elements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortedUnique<T>(pub Vec<T>);
+
+impl<T> Default for SortedUnique<T> {
+  fn default() -> Self { SortedUnique(vec![]) }
+}
+
+impl<T: FromStr + PartialOrd> Flaggable for SortedUnique<T> {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let parts: Vec<&str> = s.split(',').collect();
+    let items = parts
+      .iter()
+      .map(|v| T::from_str(v).map_err(|_| format!("invalid element \"{}\"", v)))
+      .collect::<Result<Vec<_>, _>>()?;
+    for (i, w) in items.windows(2).enumerate() {
+      if w[0] == w[1] {
+        return Err(format!("duplicate element \"{}\"", parts[i + 1]));
+      }
+      if w[0] > w[1] {
+        return Err(format!(
+          "\"{}\" is out of order after \"{}\"",
+          parts[i + 1],
+          parts[i]
+        ));
+      }
+    }
+    self.0 = items;
+    Ok(())
+  }
+}
+
+/// Direction to sort in, either `asc` or `desc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+  Asc,
+  Desc,
+}
+
+impl Direction {
+  /// Applies this direction to an ascending comparison, reversing it if descending.
+  pub fn apply(self, o: Ordering) -> Ordering {
+    match self {
+      Direction::Asc => o,
+      Direction::Desc => o.reverse(),
+    }
+  }
+}
+
+impl FromStr for Direction {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "asc" => Ok(Direction::Asc),
+      "desc" => Ok(Direction::Desc),
+      _ => Err(format!("invalid direction \"{}\", expected asc or desc", s)),
+    }
+  }
+}
+
+/// How to sort, either a single direction such as `desc`,
+/// or a comma separated list of fields and directions such as `name:asc,age:desc`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SortSpec {
+  Simple(Direction),
+  Keys(Vec<(String, Direction)>),
+}
+
+impl FromStr for SortSpec {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if let Ok(d) = Direction::from_str(s) {
+      return Ok(SortSpec::Simple(d));
+    }
+    let key = |k: &str| match k.split_once(':') {
+      Some((field, dir)) if !field.is_empty() => Ok((field.to_string(), dir.parse()?)),
+      _ => Err(format!("expected field:direction, got \"{}\"", k)),
+    };
+    let keys = s.split(',').map(key).collect::<Result<_, _>>()?;
+    Ok(SortSpec::Keys(keys))
+  }
+}
+
+/// A logging verbosity, from `off` to `trace`, matched case insensitively.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+  Off,
+  Error,
+  Warn,
+  Info,
+  Debug,
+  Trace,
+}
+
+impl FromStr for LogLevel {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "off" => Ok(LogLevel::Off),
+      "error" => Ok(LogLevel::Error),
+      "warn" => Ok(LogLevel::Warn),
+      "info" => Ok(LogLevel::Info),
+      "debug" => Ok(LogLevel::Debug),
+      "trace" => Ok(LogLevel::Trace),
+      _ => Err(format!("unknown log level \"{}\"", s)),
+    }
+  }
+}
+
+/// Log levels per target in the style of `RUST_LOG`, such as `warn,mymod=debug,mymod::db=trace`.
+/// A directive without a target sets the default level.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LogFilter {
+  pub default: Option<LogLevel>,
+  pub targets: HashMap<String, LogLevel>,
+}
+
+impl LogFilter {
+  /// The level for a target, from the directive for the longest matching module path.
+  pub fn level_for(&self, target: &str) -> Option<LogLevel> {
+    self
+      .targets
+      .iter()
+      .filter(|(t, _)| {
+        target
+          .strip_prefix(t.as_str())
+          .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+      })
+      .max_by_key(|(t, _)| t.len())
+      .map(|(_, &l)| l)
+      .or(self.default)
+  }
+}
+
+impl FromStr for LogFilter {
+  type Err = String;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut filter = LogFilter::default();
+    for directive in s.split(',') {
+      match directive.split_once('=') {
+        Some((target, level)) if !target.is_empty() => {
+          filter.targets.insert(target.to_string(), level.parse()?);
+        },
+        Some(_) => return Err(format!("missing target in \"{}\"", directive)),
+        None => filter.default = Some(directive.parse()?),
+      }
+    }
+    Ok(filter)
+  }
+}
+
+/// Tries several flags in order, parsing into the first which accepts the value,
+/// such as accepting either a number or a named constant.
+pub struct AnyOf<'f> {
+  pub parsers: Vec<&'f mut dyn Flaggable>,
+  /// Position of the parser which accepted the last value.
+  pub matched: Option<usize>,
+}
+
+impl<'f> AnyOf<'f> {
+  pub fn new(parsers: Vec<&'f mut dyn Flaggable>) -> Self {
+    AnyOf {
+      parsers,
+      matched: None,
+    }
+  }
+}
+
+impl Flaggable for AnyOf<'_> {
+  fn parse_from(&mut self, s: &str) -> Result<(), String> {
+    let mut errs = vec![];
+    for (i, p) in self.parsers.iter_mut().enumerate() {
+      match p.parse_from(s) {
+        Ok(()) => {
+          self.matched = Some(i);
+          return Ok(());
+        },
+        Err(e) => errs.push(e),
+      }
+    }
+    Err(format!("no format accepted \"{}\": {}", s, errs.join("; ")))
+  }
+}